@@ -0,0 +1,94 @@
+use dotenvy::dotenv;
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome},
+    Request,
+};
+use serde::Deserialize;
+use std::{env, fs};
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+enum KeySource {
+    PublicKey(DecodingKey),
+    Jwks(JwkSet),
+}
+
+pub struct Authenticator {
+    keys: KeySource,
+    validation: Validation,
+}
+
+impl Authenticator {
+    pub fn from_env() -> Authenticator {
+        dotenv().ok();
+        let keys = match env::var("CLERK_JWKS_PATH") {
+            Ok(path) => {
+                let jwks =
+                    fs::read_to_string(&path).unwrap_or_else(|_| panic!("Error reading {}", path));
+                KeySource::Jwks(serde_json::from_str(&jwks).expect("Invalid JWKS"))
+            }
+            Err(_) => {
+                let pem = env::var("CLERK_PEM_PUBLIC_KEY")
+                    .expect("CLERK_JWKS_PATH or CLERK_PEM_PUBLIC_KEY must be set");
+                KeySource::PublicKey(
+                    DecodingKey::from_rsa_pem(pem.as_bytes()).expect("Invalid public key"),
+                )
+            }
+        };
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        if let Ok(issuer) = env::var("CLERK_ISSUER") {
+            validation.set_issuer(&[issuer]);
+        }
+
+        Authenticator { keys, validation }
+    }
+
+    pub fn verify(&self, token: &str) -> Option<String> {
+        let key = match &self.keys {
+            KeySource::PublicKey(key) => key.clone(),
+            KeySource::Jwks(jwks) => {
+                let kid = decode_header(token).ok()?.kid?;
+                DecodingKey::from_jwk(jwks.find(&kid)?).ok()?
+            }
+        };
+        decode::<Claims>(token, &key, &self.validation)
+            .ok()
+            .map(|data| data.claims.sub)
+    }
+}
+
+pub struct AuthenticatedPlayer {
+    pub player_id: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthenticatedPlayer {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let authenticator = match request.rocket().state::<Authenticator>() {
+            Some(authenticator) => authenticator,
+            None => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        // Browsers cannot set headers on a WebSocket handshake, so the token may also be
+        // passed as a query parameter.
+        let token = match request.headers().get_one("Authorization") {
+            Some(header) => header.strip_prefix("Bearer "),
+            None => request
+                .query_value::<&str>("token")
+                .and_then(|token| token.ok()),
+        };
+
+        match token.and_then(|token| authenticator.verify(token)) {
+            Some(player_id) => Outcome::Success(AuthenticatedPlayer { player_id }),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
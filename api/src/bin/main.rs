@@ -1,14 +1,27 @@
+use api::{
+    auth::Authenticator,
+    handler::RematchConfig,
+    hub::GameHub,
+    matchmaking::{Matchmaker, MatchmakingConfig},
+    presence::{HeartbeatConfig, Presence},
+    rest::blocking,
+    scheduler::RoundScheduler,
+};
+use infrastructure::Pools;
 use rocket::{
+    fairing::AdHoc,
     http::Status,
     response::{content, status},
+    State,
 };
 
 #[macro_use]
 extern crate rocket;
 
 #[get("/")]
-fn index() -> status::Custom<content::RawJson<String>> {
-    let game = service::game_service::get_game(Some(1), None);
+async fn index(pools: &State<Pools>) -> status::Custom<content::RawJson<String>> {
+    let pools = pools.inner().clone();
+    let game = blocking(move || service::game_service::get_game(&pools, Some(1), None)).await;
     match game {
         Ok(game) => {
             let final_game = serde_json::to_string(&game).unwrap();
@@ -23,5 +36,83 @@ fn index() -> status::Custom<content::RawJson<String>> {
 
 #[launch]
 fn rocket() -> _ {
-    rocket::build().mount("/", routes![index])
+    let pools = Pools::from_env().unwrap_or_else(|e| panic!("{}", e));
+    if std::env::var("RUN_MIGRATIONS").is_ok_and(|run| run == "true") {
+        pools
+            .run_pending_migrations()
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+    let hub = GameHub::new();
+    let scheduler = RoundScheduler::new(hub.clone(), pools.clone());
+    let presence = Presence::new(hub.clone(), pools.clone(), HeartbeatConfig::from_env());
+    let matchmaker = Matchmaker::new(pools.clone(), MatchmakingConfig::from_env());
+    let topic_pools = pools.clone();
+
+    rocket::build()
+        .manage(Authenticator::from_env())
+        .manage(pools)
+        .manage(hub)
+        .manage(scheduler)
+        .manage(presence)
+        .manage(matchmaker)
+        .manage(RematchConfig::from_env())
+        .attach(AdHoc::on_liftoff("Load topic catalog", move |_| {
+            Box::pin(async move {
+                let loaded = rocket::tokio::task::spawn_blocking(move || {
+                    match std::env::var("TOPICS_FILE") {
+                        Ok(path) => {
+                            service::topic_service::load_topics_from_file(&topic_pools, &path)
+                        }
+                        Err(_) => service::topic_service::load_bundled_topics(&topic_pools),
+                    }
+                })
+                .await;
+                match loaded {
+                    Ok(Ok(_)) => (),
+                    Ok(Err(e)) => println!("Could not load the topic catalog: {}", e),
+                    Err(_) => println!("Could not load the topic catalog"),
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("Start matchmaking", |rocket| {
+            Box::pin(async move {
+                if let Some(matchmaker) = rocket.state::<Matchmaker>() {
+                    matchmaker.start();
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("Resume round timers", |rocket| {
+            Box::pin(async move {
+                if let Some(scheduler) = rocket.state::<RoundScheduler>() {
+                    scheduler.resume_open_rounds().await;
+                }
+            })
+        }))
+        .mount(
+            "/",
+            routes![
+                index,
+                api::alternative_game_stream,
+                api::matchmaking::matchmaking_stream,
+                api::matchmaking::join_queue,
+                api::matchmaking::leave_queue,
+                api::matchmaking::get_match,
+                api::games::create_game,
+                api::games::kick_player,
+                api::games::ban_player,
+                api::games::request_rematch,
+                api::games::create_invite,
+                api::games::revoke_invite,
+                api::games::resolve_invite,
+                api::games::get_game,
+                api::games::get_game_by_slug,
+                api::games::get_active_games,
+                api::games::get_game_state,
+                api::games::get_players,
+                api::games::get_game_stats,
+                api::games::get_player_stats,
+                api::games::get_current_round,
+                api::games::get_round_stats
+            ],
+        )
 }
@@ -0,0 +1,248 @@
+use crate::{
+    auth::AuthenticatedPlayer,
+    handler::{self, RematchConfig},
+    hub::GameHub,
+    presence::Presence,
+    relations,
+    rest::{blocking, respond, JsonResponse},
+};
+use infrastructure::Pools;
+use rocket::{delete, get, http::Status, post, serde::json::Json, State};
+use service::{game_service, game_state_service, invite_service, player_service};
+use shared::{request::CreateGame, response::RematchStatus, RequestOptions};
+
+#[post("/games", data = "<body>")]
+pub async fn create_game(
+    body: Json<CreateGame>,
+    player: AuthenticatedPlayer,
+    pools: &State<Pools>,
+) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let game =
+        blocking(move || game_service::create_game(&pools, player.player_id, body.into_inner()))
+            .await;
+    let relations = game.as_ref().map(relations::game).unwrap_or_default();
+    respond(Status::Created, game, relations)
+}
+
+#[post("/games/<id>/players/<pid>/kick")]
+pub async fn kick_player(
+    id: i32,
+    pid: &str,
+    player: AuthenticatedPlayer,
+    pools: &State<Pools>,
+    hub: &State<GameHub>,
+    presence: &State<Presence>,
+) -> JsonResponse {
+    let (pools, hub, presence) = (
+        pools.inner().clone(),
+        hub.inner().clone(),
+        presence.inner().clone(),
+    );
+    let target_id = pid.to_string();
+    let result = blocking(move || {
+        handler::remove_player(
+            &pools,
+            &hub,
+            &presence,
+            id,
+            &player.player_id,
+            &target_id,
+            false,
+        )
+    })
+    .await;
+    respond(Status::Ok, result, relations::players(id))
+}
+
+#[post("/games/<id>/players/<pid>/ban")]
+pub async fn ban_player(
+    id: i32,
+    pid: &str,
+    player: AuthenticatedPlayer,
+    pools: &State<Pools>,
+    hub: &State<GameHub>,
+    presence: &State<Presence>,
+) -> JsonResponse {
+    let (pools, hub, presence) = (
+        pools.inner().clone(),
+        hub.inner().clone(),
+        presence.inner().clone(),
+    );
+    let target_id = pid.to_string();
+    let result = blocking(move || {
+        handler::remove_player(
+            &pools,
+            &hub,
+            &presence,
+            id,
+            &player.player_id,
+            &target_id,
+            true,
+        )
+    })
+    .await;
+    respond(Status::Ok, result, relations::players(id))
+}
+
+#[post("/games/<id>/rematch")]
+pub async fn request_rematch(
+    id: i32,
+    player: AuthenticatedPlayer,
+    pools: &State<Pools>,
+    hub: &State<GameHub>,
+    rematch: &State<RematchConfig>,
+) -> JsonResponse {
+    let (pools, hub, rematch) = (pools.inner().clone(), hub.inner().clone(), *rematch.inner());
+    let result =
+        blocking(move || handler::request_rematch(&pools, &hub, &rematch, id, &player.player_id))
+            .await;
+    let relations = match &result {
+        Ok(rematch) => rematch
+            .game
+            .as_ref()
+            .map(relations::game)
+            .unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+    let status = result.map(|rematch| RematchStatus {
+        accepted: rematch.accepted,
+        needed: rematch.needed,
+        game_id: rematch.game.map(|game| game.id),
+    });
+    respond(Status::Ok, status, relations)
+}
+
+#[post("/games/<id>/invites")]
+pub async fn create_invite(
+    id: i32,
+    player: AuthenticatedPlayer,
+    pools: &State<Pools>,
+) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let invite =
+        blocking(move || invite_service::create_invite(&pools, id, player.player_id)).await;
+    let relations = invite.as_ref().map(relations::invite).unwrap_or_default();
+    respond(Status::Created, invite, relations)
+}
+
+#[delete("/games/<id>/invites/<code>")]
+pub async fn revoke_invite(
+    id: i32,
+    code: &str,
+    player: AuthenticatedPlayer,
+    pools: &State<Pools>,
+) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let code = code.to_string();
+    let result =
+        blocking(move || invite_service::revoke_invite(&pools, id, player.player_id, code)).await;
+    respond(Status::Ok, result, relations::players(id))
+}
+
+#[get("/invites/<code>")]
+pub async fn resolve_invite(code: &str, pools: &State<Pools>) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let code = code.to_string();
+    let game = blocking(move || invite_service::resolve_invite(&pools, code)).await;
+    let relations = game.as_ref().map(relations::game).unwrap_or_default();
+    respond(Status::Ok, game, relations)
+}
+
+#[get("/games/<id>")]
+pub async fn get_game(id: i32, pools: &State<Pools>) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let game = blocking(move || game_service::get_game(&pools, Some(id), None)).await;
+    let relations = game.as_ref().map(relations::game).unwrap_or_default();
+    respond(Status::Ok, game, relations)
+}
+
+#[get("/games/slug/<slug>", rank = 2)]
+pub async fn get_game_by_slug(slug: &str, pools: &State<Pools>) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let slug = slug.to_string();
+    let game = blocking(move || game_service::get_game(&pools, None, Some(slug))).await;
+    let relations = game.as_ref().map(relations::game).unwrap_or_default();
+    respond(Status::Ok, game, relations)
+}
+
+#[get("/games/active?<limit>&<page>")]
+pub async fn get_active_games(
+    limit: Option<i32>,
+    page: Option<i32>,
+    pools: &State<Pools>,
+) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let options = RequestOptions::new(limit, page);
+    let games = blocking(move || game_service::get_active_games(&pools, Some(options))).await;
+    let relations = games
+        .as_ref()
+        .map(|games| games.iter().flat_map(relations::game).collect())
+        .unwrap_or_default();
+    respond(Status::Ok, games, relations)
+}
+
+#[get("/games/<id>/state")]
+pub async fn get_game_state(id: i32, pools: &State<Pools>) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let state = blocking(move || game_state_service::get_game_state(&pools, id)).await;
+    let relations = state
+        .as_ref()
+        .map(relations::game_state)
+        .unwrap_or_default();
+    respond(Status::Ok, state, relations)
+}
+
+#[get("/games/<id>/players")]
+pub async fn get_players(id: i32, pools: &State<Pools>) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let players = blocking(move || player_service::get_players_in_game(&pools, id)).await;
+    respond(Status::Ok, players, relations::players(id))
+}
+
+#[get("/games/<id>/stats")]
+pub async fn get_game_stats(id: i32, pools: &State<Pools>) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let stats = blocking(move || player_service::get_player_stats_per_game_all(&pools, id)).await;
+    respond(Status::Ok, stats, relations::stats(id))
+}
+
+#[get("/games/<id>/players/<pid>/stats")]
+pub async fn get_player_stats(id: i32, pid: &str, pools: &State<Pools>) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let player_id = pid.to_string();
+    let stats =
+        blocking(move || player_service::get_player_stats_per_game(&pools, id, player_id)).await;
+    respond(Status::Ok, stats, relations::player(id, pid))
+}
+
+#[get("/games/<id>/rounds/current")]
+pub async fn get_current_round(id: i32, pools: &State<Pools>) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let result = blocking(move || {
+        let round = game_state_service::get_current_round(&pools, id)?;
+        let game = game_service::get_game(&pools, Some(id), None)?;
+        Ok((round, game))
+    })
+    .await;
+    let relations = result
+        .as_ref()
+        .map(|(round, game)| relations::round(round, game.status.clone(), game.rounds))
+        .unwrap_or_default();
+    respond(Status::Ok, result.map(|(round, _)| round), relations)
+}
+
+#[get("/games/<id>/rounds/<round_id>/stats")]
+pub async fn get_round_stats(id: i32, round_id: i32, pools: &State<Pools>) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let stats = blocking(move || {
+        player_service::get_player_stats_per_round_all(&pools, id, round_id).map(|stats| {
+            stats
+                .into_iter()
+                .map(player_service::to_round_stats)
+                .collect::<Vec<_>>()
+        })
+    })
+    .await;
+    respond(Status::Ok, stats, relations::stats(id))
+}
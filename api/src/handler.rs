@@ -0,0 +1,355 @@
+use crate::{
+    hub::GameHub,
+    presence::Presence,
+    scheduler::{round_finished, RoundScheduler},
+};
+use infrastructure::Pools;
+use service::{
+    game_service::{self, Rematch},
+    game_state_service::{self, RoundResults},
+    player_service::{self, LeftGame},
+};
+use shared::{
+    error::{Error, ErrorKind},
+    game_status::GameStatus,
+    request::{ClientMessage, RoundResult},
+    response::{
+        GameAbandoned, GameFinished, GamePaused, GameResumed, GameStarted, HostChanged,
+        NextRoundStarted, PlayerJoined, PlayerKicked, PlayerLeft, PlayerReady, RealtimeResponse,
+        RematchRequested, RematchStarted, RoundResultSent, ServerMessage,
+    },
+};
+use std::env;
+
+#[derive(Clone, Copy)]
+pub struct RematchConfig {
+    pub accept_ratio: f64,
+}
+
+impl RematchConfig {
+    // Tuned with REMATCH_ACCEPT_RATIO, the share of players that has to accept a rematch.
+    pub fn from_env() -> RematchConfig {
+        let accept_ratio = env::var("REMATCH_ACCEPT_RATIO")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|ratio| *ratio > 0.0 && *ratio <= 1.0)
+            .unwrap_or(0.5);
+        RematchConfig { accept_ratio }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct EventContext<'a> {
+    pub pools: &'a Pools,
+    pub hub: &'a GameHub,
+    pub scheduler: &'a RoundScheduler,
+    pub presence: &'a Presence,
+    pub rematch: &'a RematchConfig,
+    pub game_id: i32,
+    pub player_id: &'a str,
+}
+
+pub fn handle_event(ctx: EventContext, message: ClientMessage) -> Result<(), Error> {
+    let EventContext {
+        pools,
+        hub,
+        scheduler,
+        presence,
+        rematch,
+        game_id,
+        player_id,
+    } = ctx;
+
+    match message {
+        ClientMessage::JoinGame(access) => {
+            player_service::join_game(
+                pools,
+                game_id,
+                player_id.to_string(),
+                access.unwrap_or_default(),
+            )?;
+            hub.publish(
+                game_id,
+                ServerMessage::PlayerJoined(PlayerJoined {
+                    player_id: player_id.to_string(),
+                }),
+            );
+        }
+        ClientMessage::LeaveGame => {
+            let left = player_service::leave_game(pools, game_id, player_id.to_string())?;
+            announce_leave(hub, game_id, player_id, left);
+        }
+        ClientMessage::StartGame => {
+            let round = game_state_service::start_game(pools, game_id, player_id.to_string())?;
+            scheduler.schedule(game_id, round.id);
+            hub.publish(
+                game_id,
+                ServerMessage::GameStarted(GameStarted {
+                    game_status: GameStatus::to_string(GameStatus::PLAYING),
+                    round_number: round.round_number,
+                    topic: round.topic,
+                    start_time: round.start_time.and_utc().timestamp_millis(),
+                }),
+            );
+        }
+        ClientMessage::FinishRound(result) => {
+            let round =
+                game_state_service::finish_round(pools, round_results(game_id, player_id, result))?;
+            hub.publish(game_id, ServerMessage::RoundFinished(round_finished(round)));
+        }
+        ClientMessage::StartNextRound => {
+            let round = game_state_service::start_new_round(pools, game_id, player_id.to_string())?;
+            scheduler.schedule(game_id, round.id);
+            hub.publish(
+                game_id,
+                ServerMessage::NextRoundStarted(NextRoundStarted {
+                    round_number: round.round_number,
+                    topic: round.topic,
+                    start_time: round.start_time.and_utc().timestamp_millis(),
+                }),
+            );
+        }
+        ClientMessage::FinishGame => {
+            game_state_service::change_to_finished(pools, game_id, player_id.to_string())?;
+            let player_stats = player_service::get_player_stats_per_game_all(pools, game_id)?;
+            hub.publish(
+                game_id,
+                ServerMessage::GameFinished(GameFinished {
+                    game_status: GameStatus::to_string(GameStatus::FINISHED),
+                    player_stats,
+                }),
+            );
+        }
+        ClientMessage::KickPlayer(target) => {
+            remove_player(
+                pools,
+                hub,
+                presence,
+                game_id,
+                player_id,
+                &target.player_id,
+                false,
+            )?;
+        }
+        ClientMessage::BanPlayer(target) => {
+            remove_player(
+                pools,
+                hub,
+                presence,
+                game_id,
+                player_id,
+                &target.player_id,
+                true,
+            )?;
+        }
+        ClientMessage::SetReady => set_ready(pools, hub, game_id, player_id, true)?,
+        ClientMessage::UnsetReady => set_ready(pools, hub, game_id, player_id, false)?,
+        ClientMessage::PauseGame => {
+            let paused = game_state_service::pause_game(pools, game_id, player_id.to_string())?;
+            hub.publish(
+                game_id,
+                ServerMessage::GamePaused(GamePaused {
+                    game_status: GameStatus::to_string(GameStatus::PAUSED),
+                    remaining_time: paused.remaining_time,
+                }),
+            );
+        }
+        ClientMessage::ResumeGame => {
+            let resumed = game_state_service::resume_game(pools, game_id, player_id.to_string())?;
+            if let Some(round_id) = resumed.round_id {
+                scheduler.schedule(game_id, round_id);
+            }
+            hub.publish(
+                game_id,
+                ServerMessage::GameResumed(GameResumed {
+                    game_status: GameStatus::to_string(GameStatus::PLAYING),
+                    remaining_time: resumed.remaining_time,
+                }),
+            );
+        }
+        ClientMessage::RequestRematch => {
+            request_rematch(pools, hub, rematch, game_id, player_id)?;
+        }
+        // Resuming only concerns the connection that asked for it and is answered directly.
+        ClientMessage::Resume(_) => (),
+        ClientMessage::SendRoundResult(result) => {
+            let round =
+                game_state_service::send_results(pools, round_results(game_id, player_id, result))?;
+            let player_stats =
+                player_service::get_player_stats_per_round_all(pools, game_id, round.id)?
+                    .into_iter()
+                    .map(player_service::to_round_stats)
+                    .collect();
+            hub.publish(
+                game_id,
+                ServerMessage::RoundResultSent(RoundResultSent {
+                    round_number: round.round_number,
+                    topic: round.topic,
+                    start_time: round.start_time.and_utc().timestamp_millis(),
+                    end_time: round
+                        .end_time
+                        .map(|end_time| end_time.and_utc().timestamp_millis())
+                        .unwrap_or_default(),
+                    player_stats,
+                }),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn set_ready(
+    pools: &Pools,
+    hub: &GameHub,
+    game_id: i32,
+    player_id: &str,
+    ready: bool,
+) -> Result<(), Error> {
+    player_service::set_ready(pools, game_id, player_id.to_string(), ready)?;
+    hub.publish(
+        game_id,
+        ServerMessage::PlayerReady(PlayerReady {
+            player_id: player_id.to_string(),
+            ready,
+        }),
+    );
+    Ok(())
+}
+
+pub fn remove_player(
+    pools: &Pools,
+    hub: &GameHub,
+    presence: &Presence,
+    game_id: i32,
+    host_id: &str,
+    target_id: &str,
+    ban: bool,
+) -> Result<(), Error> {
+    if ban {
+        player_service::ban_player(pools, game_id, host_id.to_string(), target_id.to_string())?;
+    } else {
+        player_service::kick_player(pools, game_id, host_id.to_string(), target_id.to_string())?;
+    }
+
+    hub.publish(
+        game_id,
+        ServerMessage::PlayerKicked(PlayerKicked {
+            player_id: target_id.to_string(),
+            banned: ban,
+        }),
+    );
+    let reason = if ban {
+        "Banned by the host"
+    } else {
+        "Kicked by the host"
+    };
+    presence.kick(game_id, target_id, reason);
+    Ok(())
+}
+
+pub fn request_rematch(
+    pools: &Pools,
+    hub: &GameHub,
+    config: &RematchConfig,
+    game_id: i32,
+    player_id: &str,
+) -> Result<Rematch, Error> {
+    let rematch =
+        game_service::request_rematch(pools, game_id, player_id.to_string(), config.accept_ratio)?;
+    match &rematch.game {
+        Some(game) if rematch.created => hub.publish(
+            game_id,
+            ServerMessage::RematchStarted(RematchStarted {
+                game_id: game.id,
+                game_slug: game.game_slug.clone(),
+                previous_game_id: game_id,
+            }),
+        ),
+        Some(game) => hub.publish(
+            game.id,
+            ServerMessage::PlayerJoined(PlayerJoined {
+                player_id: player_id.to_string(),
+            }),
+        ),
+        None => hub.publish(
+            game_id,
+            ServerMessage::RematchRequested(RematchRequested {
+                player_id: player_id.to_string(),
+                accepted: rematch.accepted,
+                needed: rematch.needed,
+            }),
+        ),
+    }
+    Ok(rematch)
+}
+
+pub fn announce_leave(hub: &GameHub, game_id: i32, player_id: &str, left: LeftGame) {
+    hub.publish(
+        game_id,
+        ServerMessage::PlayerLeft(PlayerLeft {
+            player_id: player_id.to_string(),
+        }),
+    );
+    if let Some(new_host) = left.new_host {
+        hub.publish(
+            game_id,
+            ServerMessage::HostChanged(HostChanged {
+                player_id: new_host,
+            }),
+        );
+    }
+    if left.abandoned {
+        hub.publish(
+            game_id,
+            ServerMessage::GameAbandoned(GameAbandoned {
+                game_status: GameStatus::to_string(GameStatus::ABANDONED),
+            }),
+        );
+    }
+}
+
+// Replays the events a reconnecting client missed, or sends a snapshot of the game when the
+// log no longer covers the gap.
+pub fn resume(
+    pools: &Pools,
+    hub: &GameHub,
+    game_id: i32,
+    last_seq: u64,
+) -> Result<(Vec<String>, u64), Error> {
+    match hub.replay(game_id, last_seq) {
+        Some(events) => {
+            let seq = events.last().map_or(last_seq, |event| event.seq);
+            Ok((events.into_iter().map(|event| event.payload).collect(), seq))
+        }
+        None => snapshot(pools, hub, game_id),
+    }
+}
+
+// The snapshot carries the sequence number of the latest broadcast it already reflects.
+pub fn snapshot(pools: &Pools, hub: &GameHub, game_id: i32) -> Result<(Vec<String>, u64), Error> {
+    let seq = hub.current_seq(game_id);
+    let state = game_state_service::get_game_state(pools, game_id)?;
+    match serde_json::to_string(&RealtimeResponse::with_seq(
+        ServerMessage::GameState(state),
+        seq,
+    )) {
+        Ok(payload) => Ok((vec![payload], seq)),
+        Err(e) => Err(Error::with_cause(
+            ErrorKind::Internal,
+            String::from("Could not encode the game state"),
+            e,
+        )),
+    }
+}
+
+fn round_results(game_id: i32, player_id: &str, result: RoundResult) -> RoundResults {
+    RoundResults {
+        game_id,
+        player_id: player_id.to_string(),
+        round_number: result.round_number,
+        first_topic: result.first_topic,
+        second_topic: result.second_topic,
+        third_topic: result.third_topic,
+        received: chrono::Utc::now().naive_utc(),
+    }
+}
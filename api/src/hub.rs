@@ -0,0 +1,183 @@
+use rocket::tokio::sync::broadcast::{self, Receiver, Sender};
+use shared::response::{RealtimeResponse, ServerMessage};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const CHANNEL_CAPACITY: usize = 64;
+const EVENT_LOG_CAPACITY: usize = 128;
+const IDLE_RETENTION: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Clone)]
+pub struct HubEvent {
+    pub seq: u64,
+    pub payload: String,
+}
+
+struct GameChannel {
+    sender: Sender<HubEvent>,
+    seq: u64,
+    log: VecDeque<HubEvent>,
+    last_active: Instant,
+}
+
+impl GameChannel {
+    fn new() -> GameChannel {
+        GameChannel {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            seq: 0,
+            log: VecDeque::with_capacity(EVENT_LOG_CAPACITY),
+            last_active: Instant::now(),
+        }
+    }
+}
+
+// Channels outlive their subscribers for a while, so clients reconnecting shortly after
+// dropping can still replay what they missed.
+#[derive(Clone, Default)]
+pub struct GameHub {
+    channels: Arc<Mutex<HashMap<i32, GameChannel>>>,
+}
+
+impl GameHub {
+    pub fn new() -> GameHub {
+        GameHub::default()
+    }
+
+    pub fn subscribe(&self, game_id: i32) -> Receiver<HubEvent> {
+        let mut channels = self.channels.lock().unwrap();
+        let channel = channels.entry(game_id).or_insert_with(GameChannel::new);
+        channel.last_active = Instant::now();
+        channel.sender.subscribe()
+    }
+
+    pub fn unsubscribe(&self, game_id: i32) {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(channel) = channels.get_mut(&game_id) {
+            channel.last_active = Instant::now();
+        }
+        channels.retain(|_, channel| {
+            channel.sender.receiver_count() > 0 || channel.last_active.elapsed() < IDLE_RETENTION
+        });
+    }
+
+    pub fn publish(&self, game_id: i32, message: ServerMessage) {
+        let mut channels = self.channels.lock().unwrap();
+        let channel = channels.entry(game_id).or_insert_with(GameChannel::new);
+        let seq = channel.seq + 1;
+        let payload = match serde_json::to_string(&RealtimeResponse::with_seq(message, seq)) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+
+        let event = HubEvent { seq, payload };
+        channel.seq = seq;
+        channel.last_active = Instant::now();
+        if channel.log.len() == EVENT_LOG_CAPACITY {
+            channel.log.pop_front();
+        }
+        channel.log.push_back(event.clone());
+        // Sending only fails when nobody is subscribed, which is fine for a broadcast.
+        let _ = channel.sender.send(event);
+    }
+
+    pub fn current_seq(&self, game_id: i32) -> u64 {
+        let channels = self.channels.lock().unwrap();
+        channels.get(&game_id).map_or(0, |channel| channel.seq)
+    }
+
+    // Returns the events after `last_seq`, or `None` when some of them are no longer in the
+    // log and the client has to start over from a snapshot.
+    pub fn replay(&self, game_id: i32, last_seq: u64) -> Option<Vec<HubEvent>> {
+        let channels = self.channels.lock().unwrap();
+        let channel = match channels.get(&game_id) {
+            Some(channel) => channel,
+            None => {
+                return if last_seq == 0 {
+                    Some(Vec::new())
+                } else {
+                    None
+                }
+            }
+        };
+        if last_seq > channel.seq {
+            return None;
+        }
+
+        let oldest = channel
+            .log
+            .front()
+            .map_or(channel.seq + 1, |event| event.seq);
+        if last_seq + 1 < oldest {
+            return None;
+        }
+        Some(
+            channel
+                .log
+                .iter()
+                .filter(|event| event.seq > last_seq)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::response::GameAbandoned;
+
+    fn publish(hub: &GameHub, game_id: i32, count: usize) {
+        for _ in 0..count {
+            hub.publish(
+                game_id,
+                ServerMessage::GameAbandoned(GameAbandoned {
+                    game_status: String::from("abandoned"),
+                }),
+            );
+        }
+    }
+
+    fn seqs(events: Option<Vec<HubEvent>>) -> Option<Vec<u64>> {
+        events.map(|events| events.iter().map(|event| event.seq).collect())
+    }
+
+    #[test]
+    fn replays_events_inside_the_window() {
+        let hub = GameHub::new();
+        publish(&hub, 1, 5);
+
+        assert_eq!(seqs(hub.replay(1, 2)), Some(vec![3, 4, 5]));
+        assert_eq!(seqs(hub.replay(1, 0)), Some(vec![1, 2, 3, 4, 5]));
+        assert!(hub.replay(1, 3).unwrap()[0].payload.contains(r#""seq":4"#));
+    }
+
+    #[test]
+    fn gap_older_than_the_log_needs_a_snapshot() {
+        let hub = GameHub::new();
+        publish(&hub, 1, EVENT_LOG_CAPACITY + 10);
+
+        assert!(hub.replay(1, 0).is_none());
+        assert!(hub.replay(1, 9).is_none());
+        assert_eq!(
+            seqs(hub.replay(1, 10)).map(|seqs| seqs.len()),
+            Some(EVENT_LOG_CAPACITY)
+        );
+    }
+
+    #[test]
+    fn empty_replay_keeps_the_current_seq() {
+        let hub = GameHub::new();
+        assert_eq!(seqs(hub.replay(1, 0)), Some(Vec::new()));
+        assert!(hub.replay(1, 3).is_none());
+        assert_eq!(hub.current_seq(1), 0);
+
+        publish(&hub, 1, 3);
+        assert_eq!(seqs(hub.replay(1, 3)), Some(Vec::new()));
+        assert_eq!(hub.current_seq(1), 3);
+        // A client claiming to be ahead of the game has to start over.
+        assert!(hub.replay(1, 4).is_none());
+    }
+}
@@ -1,99 +1,258 @@
+use auth::AuthenticatedPlayer;
+use handler::{EventContext, RematchConfig};
+use hub::GameHub;
+use infrastructure::Pools;
+use presence::Presence;
+use rest::blocking;
 use rocket::{
     futures::{FutureExt, SinkExt, StreamExt},
     get,
-    http::Status,
+    tokio::{
+        select,
+        sync::broadcast::error::RecvError,
+        time::{self, Instant},
+    },
+    State,
 };
+use scheduler::RoundScheduler;
 use shared::{
-    request::{Request, RequestEvent},
-    response::{ErrorRes, RealtimeResponse, ResponseEvents},
+    error::{Error, ErrorKind},
+    request::ClientMessage,
+    response::RealtimeResponse,
 };
-use ws::{Channel, Message, WebSocket};
+use ws::{
+    frame::{CloseCode, CloseFrame},
+    stream::DuplexStream,
+    Channel, Message, WebSocket,
+};
+
+pub mod auth;
+pub mod games;
+pub mod handler;
+pub mod hub;
+pub mod matchmaking;
+pub mod presence;
+pub mod relations;
+pub mod rest;
+pub mod scheduler;
 
-#[get("/game/<id>")]
-pub fn alternative_game_stream(ws: WebSocket, id: &str) -> Channel<'static> {
+// Reconnecting clients pass the `seq` of the last event they have seen as `last_seq`, so the
+// events they missed are replayed before any live one is forwarded.
+#[get("/game/<id>?<last_seq>")]
+#[allow(clippy::too_many_arguments)]
+pub fn alternative_game_stream(
+    ws: WebSocket,
+    id: &str,
+    last_seq: Option<u64>,
+    player: AuthenticatedPlayer,
+    pools: &State<Pools>,
+    hub: &State<GameHub>,
+    scheduler: &State<RoundScheduler>,
+    presence: &State<Presence>,
+    rematch: &State<RematchConfig>,
+) -> Channel<'static> {
     let game_id = id.to_string().parse::<i32>();
+    let player_id = player.player_id;
+    let pools = pools.inner().clone();
+    let hub = hub.inner().clone();
+    let scheduler = scheduler.inner().clone();
+    let presence = presence.inner().clone();
+    let rematch = *rematch.inner();
+    let resume_from = last_seq;
     ws.channel(move |mut stream| {
         let game_id = match game_id {
             Ok(id) => id,
             Err(_) => {
-                return stream.close(None).boxed();
+                return async move {
+                    let frame = CloseFrame {
+                        code: CloseCode::Policy,
+                        reason: "Invalid game id".into(),
+                    };
+                    stream.close(Some(frame)).await
+                }
+                .boxed();
             }
         };
 
         Box::pin(async move {
-            while let Some(msg) = stream.next().await {
-                let message = msg.unwrap();
-                println!(
-                    "Received message: {}, game id: {}",
-                    message.clone(),
-                    game_id
-                );
-                if message.is_empty() {
-                    continue;
-                }
-
-                if !message.is_text() {
-                    let err = RealtimeResponse::<ErrorRes> {
-                        event: ResponseEvents::Error.to_string(),
-                        data: None,
-                        error: Some(String::from("Invalid message type")),
-                        error_code: Some(1),
-                    };
-
-                    stream
-                        .send(Message::Text(serde_json::to_string(&err).unwrap()))
-                        .await
-                        .unwrap();
-                }
-
-                let request_obj =
-                    serde_json::from_str::<Request>(message.to_string().as_str()).unwrap();
-                let event_type = match RequestEvent::from_string(request_obj.event) {
-                    Ok(event) => event,
+            let mut events = hub.subscribe(game_id);
+            let heartbeat_config = presence.config();
+            let mut heartbeat = time::interval(heartbeat_config.interval);
+            let mut last_seen = Instant::now();
+            let mut last_seq = 0;
+            let mut kicked = presence.connect(game_id, &player_id);
+            // Subscribed first, so live events published meanwhile wait in the channel and are
+            // skipped below when the replay already covered them. A failed send shows up again
+            // on the next read and ends the loop.
+            if let Some(resume_from) = resume_from {
+                let replies = match resume(&pools, &hub, game_id, resume_from).await {
+                    Ok((replies, seq)) => {
+                        last_seq = seq;
+                        replies
+                    }
                     Err(e) => {
-                        let err = RealtimeResponse::<ErrorRes> {
-                            event: ResponseEvents::Error.to_string(),
-                            data: None,
-                            error: Some(e.message),
-                            error_code: Some(Status::BadRequest.code as i32),
-                        };
-
-                        stream
-                            .send(Message::Text(serde_json::to_string(&err).unwrap()))
-                            .await
-                            .unwrap();
-                        continue;
+                        if e.cause.is_some() {
+                            println!("Error in game {}: {}", game_id, e);
+                        }
+                        error_reply(e)
                     }
                 };
+                let _ = send_all(&mut stream, replies).await;
+            }
+            loop {
+                select! {
+                    message = stream.next() => {
+                        let message = match message {
+                            Some(Ok(message)) => message,
+                            Some(Err(e)) => {
+                                println!("Connection error in game {}: {}", game_id, e);
+                                break;
+                            }
+                            None => break,
+                        };
+                        last_seen = Instant::now();
 
-                match event_type {
-                    RequestEvent::JoinGame => {
-                        match service::player_service::join_game(game_id, player_id) {
-                            Ok(_) => (),
-                            Err(e) => {
-                                let err = RealtimeResponse::<ErrorRes> {
-                                    event: ResponseEvents::Error.to_string(),
-                                    data: None,
-                                    error: Some(e.message),
-                                    error_code: Some(Status::BadRequest.code as i32),
+                        let replies = match message {
+                            Message::Text(text) if text.is_empty() => Vec::new(),
+                            Message::Text(text) => {
+                                let ctx = EventContext {
+                                    pools: &pools,
+                                    hub: &hub,
+                                    scheduler: &scheduler,
+                                    presence: &presence,
+                                    rematch: &rematch,
+                                    game_id,
+                                    player_id: &player_id,
                                 };
+                                match handle_text(ctx, text).await {
+                                    Ok(Some((replies, seq))) => {
+                                        last_seq = seq;
+                                        replies
+                                    }
+                                    Ok(None) => Vec::new(),
+                                    Err(e) => {
+                                        if e.cause.is_some() {
+                                            println!("Error in game {}: {}", game_id, e);
+                                        }
+                                        error_reply(e)
+                                    }
+                                }
+                            }
+                            Message::Binary(_) => error_reply(Error::new(
+                                ErrorKind::InvalidMessage,
+                                String::from("Binary messages are not supported"),
+                            )),
+                            // Pings are answered by the protocol layer and pongs only keep the
+                            // connection alive.
+                            Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => Vec::new(),
+                            Message::Close(_) => break,
+                        };
 
-                                stream
-                                    .send(Message::Text(serde_json::to_string(&err).unwrap()))
-                                    .await
-                                    .unwrap();
+                        if send_all(&mut stream, replies).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(reason) = kicked.recv() => {
+                        let frame = CloseFrame {
+                            code: CloseCode::Policy,
+                            reason: reason.into(),
+                        };
+                        let _ = stream.close(Some(frame)).await;
+                        break;
+                    }
+                    _ = heartbeat.tick() => {
+                        if last_seen.elapsed() > heartbeat_config.timeout {
+                            let frame = CloseFrame {
+                                code: CloseCode::Away,
+                                reason: "Heartbeat timeout".into(),
+                            };
+                            let _ = stream.close(Some(frame)).await;
+                            break;
+                        }
+                        if stream.send(Message::Ping(Vec::new())).await.is_err() {
+                            break;
+                        }
+                    }
+                    event = events.recv() => match event {
+                        // Events already replayed after a resume are not sent twice.
+                        Ok(event) if event.seq <= last_seq => (),
+                        Ok(event) => {
+                            last_seq = event.seq;
+                            if stream.send(Message::Text(event.payload)).await.is_err() {
+                                break;
                             }
                         }
+                        Err(RecvError::Lagged(_)) => (),
+                        Err(RecvError::Closed) => break,
                     }
-                    RequestEvent::LeaveGame => {}
-                    RequestEvent::StartGame => {}
-                    RequestEvent::FinishRound => {}
-                    RequestEvent::StartNextRound => {}
-                    RequestEvent::FinishGame => {}
-                    RequestEvent::SendRoundResult => {}
                 }
             }
+            drop(events);
+            hub.unsubscribe(game_id);
+            presence.disconnect(game_id, &player_id);
             Ok(())
         })
     })
 }
+
+// Returns the replies for this client only, along with the sequence number they bring it
+// up to, when the message was a resume request or a join.
+async fn handle_text(
+    ctx: EventContext<'_>,
+    text: String,
+) -> Result<Option<(Vec<String>, u64)>, Error> {
+    let pools = ctx.pools.clone();
+    let hub = ctx.hub.clone();
+    let scheduler = ctx.scheduler.clone();
+    let presence = ctx.presence.clone();
+    let rematch = *ctx.rematch;
+    let game_id = ctx.game_id;
+    let player_id = ctx.player_id.to_string();
+    blocking(move || {
+        let ctx = EventContext {
+            pools: &pools,
+            hub: &hub,
+            scheduler: &scheduler,
+            presence: &presence,
+            rematch: &rematch,
+            game_id,
+            player_id: &player_id,
+        };
+        match ClientMessage::parse(&text)? {
+            ClientMessage::Resume(resume) => {
+                handler::resume(ctx.pools, ctx.hub, ctx.game_id, resume.last_seq).map(Some)
+            }
+            message @ ClientMessage::JoinGame(_) => {
+                handler::handle_event(ctx, message)?;
+                handler::snapshot(ctx.pools, ctx.hub, ctx.game_id).map(Some)
+            }
+            message => handler::handle_event(ctx, message).map(|_| None),
+        }
+    })
+    .await
+}
+
+async fn resume(
+    pools: &Pools,
+    hub: &GameHub,
+    game_id: i32,
+    last_seq: u64,
+) -> Result<(Vec<String>, u64), Error> {
+    let pools = pools.clone();
+    let hub = hub.clone();
+    blocking(move || handler::resume(&pools, &hub, game_id, last_seq)).await
+}
+
+fn error_reply(error: Error) -> Vec<String> {
+    match serde_json::to_string(&RealtimeResponse::error(error)) {
+        Ok(payload) => vec![payload],
+        Err(_) => Vec::new(),
+    }
+}
+
+async fn send_all(stream: &mut DuplexStream, payloads: Vec<String>) -> ws::result::Result<()> {
+    for payload in payloads {
+        stream.send(Message::Text(payload)).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use crate::{
+    auth::AuthenticatedPlayer,
+    presence::{env_seconds, Presence},
+    relations,
+    rest::{blocking, respond, JsonResponse},
+};
+use infrastructure::Pools;
+use rocket::{
+    delete,
+    futures::{SinkExt, StreamExt},
+    get,
+    http::Status,
+    post,
+    serde::json::Json,
+    tokio::{
+        self, select,
+        sync::broadcast::{self, Receiver, Sender},
+        task,
+        time::{self, Instant},
+    },
+    State,
+};
+use service::matchmaking_service;
+use shared::{
+    request::QueuePreferences,
+    response::{MatchFound, RealtimeResponse, ServerMessage},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use ws::{
+    frame::{CloseCode, CloseFrame},
+    Channel, Message, WebSocket,
+};
+
+#[derive(Clone, Copy)]
+pub struct MatchmakingConfig {
+    pub interval: Duration,
+    pub max_wait: Duration,
+}
+
+impl MatchmakingConfig {
+    // Tuned with MATCHMAKING_INTERVAL_SECONDS and MATCHMAKING_MAX_WAIT_SECONDS.
+    pub fn from_env() -> MatchmakingConfig {
+        MatchmakingConfig {
+            interval: env_seconds("MATCHMAKING_INTERVAL_SECONDS", 2),
+            max_wait: env_seconds("MATCHMAKING_MAX_WAIT_SECONDS", 30),
+        }
+    }
+}
+
+// Runs the matcher and hands the resulting games to the matchmaking streams of the
+// matched players.
+#[derive(Clone)]
+pub struct Matchmaker {
+    waiting: Arc<Mutex<HashMap<String, Sender<String>>>>,
+    pools: Pools,
+    config: MatchmakingConfig,
+}
+
+impl Matchmaker {
+    pub fn new(pools: Pools, config: MatchmakingConfig) -> Matchmaker {
+        Matchmaker {
+            waiting: Arc::new(Mutex::new(HashMap::new())),
+            pools,
+            config,
+        }
+    }
+
+    pub fn start(&self) {
+        let matchmaker = self.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(matchmaker.config.interval);
+            loop {
+                interval.tick().await;
+                let pools = matchmaker.pools.clone();
+                let max_wait = matchmaker.config.max_wait;
+                match task::spawn_blocking(move || {
+                    matchmaking_service::find_matches(&pools, max_wait)
+                })
+                .await
+                {
+                    Ok(Ok(run)) => {
+                        for e in run.failures {
+                            println!("Matchmaking failed for a group: {}", e);
+                        }
+                        for found in run.matches {
+                            matchmaker.notify(found);
+                        }
+                    }
+                    Ok(Err(e)) => println!("Matchmaking failed: {}", e),
+                    Err(_) => println!("Matchmaking failed"),
+                }
+            }
+        });
+    }
+
+    fn subscribe(&self, player_id: &str) -> Receiver<String> {
+        let mut waiting = self.waiting.lock().unwrap();
+        waiting
+            .entry(player_id.to_string())
+            .or_insert_with(|| broadcast::channel(1).0)
+            .subscribe()
+    }
+
+    // Returns whether this was the last stream of the player.
+    fn unsubscribe(&self, player_id: &str) -> bool {
+        let mut waiting = self.waiting.lock().unwrap();
+        match waiting.get(player_id) {
+            Some(sender) if sender.receiver_count() > 0 => false,
+            _ => {
+                waiting.remove(player_id);
+                true
+            }
+        }
+    }
+
+    fn notify(&self, found: matchmaking_service::Match) {
+        let message = ServerMessage::MatchFound(MatchFound {
+            game_id: found.game.id,
+            game_slug: found.game.game_slug,
+        });
+        let payload = match serde_json::to_string(&RealtimeResponse::new(message)) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+
+        let waiting = self.waiting.lock().unwrap();
+        for player_id in found.player_ids {
+            if let Some(sender) = waiting.get(&player_id) {
+                let _ = sender.send(payload.clone());
+            }
+        }
+    }
+}
+
+#[post("/matchmaking/queue", data = "<body>")]
+pub async fn join_queue(
+    body: Json<QueuePreferences>,
+    player: AuthenticatedPlayer,
+    pools: &State<Pools>,
+) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let preferences = blocking(move || {
+        matchmaking_service::join_queue(&pools, player.player_id, body.into_inner())
+    })
+    .await;
+    respond(Status::Accepted, preferences, relations::matchmaking())
+}
+
+// Polled by clients that are not connected to the matchmaking stream.
+#[get("/matchmaking/match")]
+pub async fn get_match(player: AuthenticatedPlayer, pools: &State<Pools>) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let found = blocking(move || matchmaking_service::get_match(&pools, player.player_id)).await;
+    let relations = found
+        .as_ref()
+        .map(|found| relations::match_found(found.game_id))
+        .unwrap_or_default();
+    respond(Status::Ok, found, relations)
+}
+
+#[delete("/matchmaking/queue")]
+pub async fn leave_queue(player: AuthenticatedPlayer, pools: &State<Pools>) -> JsonResponse {
+    let pools = pools.inner().clone();
+    let result = blocking(move || matchmaking_service::leave_queue(&pools, player.player_id)).await;
+    respond(Status::Ok, result, Vec::new())
+}
+
+// Waits for a match of a queued player and closes once it has been sent. Closing the last
+// stream before that takes the player out of the queue.
+#[get("/matchmaking")]
+pub fn matchmaking_stream(
+    ws: WebSocket,
+    player: AuthenticatedPlayer,
+    matchmaker: &State<Matchmaker>,
+    presence: &State<Presence>,
+) -> Channel<'static> {
+    let player_id = player.player_id;
+    let matchmaker = matchmaker.inner().clone();
+    let heartbeat_config = presence.config();
+    ws.channel(move |mut stream| {
+        Box::pin(async move {
+            let mut found = matchmaker.subscribe(&player_id);
+            let mut heartbeat = time::interval(heartbeat_config.interval);
+            let mut last_seen = Instant::now();
+            let mut matched = false;
+            loop {
+                select! {
+                    message = stream.next() => match message {
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => last_seen = Instant::now(),
+                    },
+                    Ok(payload) = found.recv() => {
+                        matched = true;
+                        let _ = stream.send(Message::Text(payload)).await;
+                        let frame = CloseFrame {
+                            code: CloseCode::Normal,
+                            reason: "Match found".into(),
+                        };
+                        let _ = stream.close(Some(frame)).await;
+                        break;
+                    }
+                    _ = heartbeat.tick() => {
+                        if last_seen.elapsed() > heartbeat_config.timeout {
+                            let frame = CloseFrame {
+                                code: CloseCode::Away,
+                                reason: "Heartbeat timeout".into(),
+                            };
+                            let _ = stream.close(Some(frame)).await;
+                            break;
+                        }
+                        if stream.send(Message::Ping(Vec::new())).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            drop(found);
+            if matchmaker.unsubscribe(&player_id) && !matched {
+                let pools = matchmaker.pools.clone();
+                let _ = task::spawn_blocking(move || {
+                    matchmaking_service::leave_queue(&pools, player_id)
+                })
+                .await;
+            }
+            Ok(())
+        })
+    })
+}
@@ -0,0 +1,149 @@
+use crate::{handler, hub::GameHub};
+use infrastructure::Pools;
+use rocket::tokio::{
+    self,
+    sync::broadcast::{self, Receiver, Sender},
+    task, time,
+};
+use service::player_service;
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Clone, Copy)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub grace_period: Duration,
+}
+
+impl HeartbeatConfig {
+    // Tuned with HEARTBEAT_INTERVAL_SECONDS, HEARTBEAT_TIMEOUT_SECONDS and
+    // DISCONNECT_GRACE_SECONDS.
+    pub fn from_env() -> HeartbeatConfig {
+        HeartbeatConfig {
+            interval: env_seconds("HEARTBEAT_INTERVAL_SECONDS", 15),
+            timeout: env_seconds("HEARTBEAT_TIMEOUT_SECONDS", 45),
+            grace_period: env_seconds("DISCONNECT_GRACE_SECONDS", 30),
+        }
+    }
+}
+
+struct Session {
+    connections: usize,
+    generation: u64,
+    // Carries the reason when the player's streams have to be closed by the server.
+    kick: Sender<String>,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            connections: 0,
+            generation: 0,
+            kick: broadcast::channel(1).0,
+        }
+    }
+}
+
+// Tracks open game streams per player, so a player whose sockets are all gone for longer
+// than the grace period is removed from the game.
+#[derive(Clone)]
+pub struct Presence {
+    sessions: Arc<Mutex<HashMap<(i32, String), Session>>>,
+    hub: GameHub,
+    pools: Pools,
+    config: HeartbeatConfig,
+}
+
+impl Presence {
+    pub fn new(hub: GameHub, pools: Pools, config: HeartbeatConfig) -> Presence {
+        Presence {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            hub,
+            pools,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> HeartbeatConfig {
+        self.config
+    }
+
+    pub fn connect(&self, game_id: i32, player_id: &str) -> Receiver<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .entry((game_id, player_id.to_string()))
+            .or_insert_with(Session::new);
+        session.connections += 1;
+        session.generation += 1;
+        session.kick.subscribe()
+    }
+
+    pub fn kick(&self, game_id: i32, player_id: &str, reason: &str) {
+        let sessions = self.sessions.lock().unwrap();
+        if let Some(session) = sessions.get(&(game_id, player_id.to_string())) {
+            let _ = session.kick.send(reason.to_string());
+        }
+    }
+
+    pub fn disconnect(&self, game_id: i32, player_id: &str) {
+        let generation = {
+            let mut sessions = self.sessions.lock().unwrap();
+            match sessions.get_mut(&(game_id, player_id.to_string())) {
+                Some(session) => {
+                    session.connections = session.connections.saturating_sub(1);
+                    if session.connections > 0 {
+                        return;
+                    }
+                    session.generation
+                }
+                None => return,
+            }
+        };
+
+        let presence = self.clone();
+        let player_id = player_id.to_string();
+        tokio::spawn(async move {
+            time::sleep(presence.config.grace_period).await;
+            if !presence.expire(game_id, &player_id, generation) {
+                return;
+            }
+
+            let pools = presence.pools.clone();
+            let leaving_id = player_id.clone();
+            let left = task::spawn_blocking(move || {
+                player_service::leave_game(&pools, game_id, leaving_id)
+            })
+            .await;
+            // Players who already left or never joined have nothing to announce.
+            if let Ok(Ok(left)) = left {
+                handler::announce_leave(&presence.hub, game_id, &player_id, left);
+            }
+        });
+    }
+
+    fn expire(&self, game_id: i32, player_id: &str, generation: u64) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        let key = (game_id, player_id.to_string());
+        match sessions.get(&key) {
+            Some(session) if session.connections == 0 && session.generation == generation => {
+                sessions.remove(&key);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+pub(crate) fn env_seconds(name: &str, default: u64) -> Duration {
+    let seconds = env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|seconds| *seconds > 0)
+        .unwrap_or(default);
+    Duration::from_secs(seconds)
+}
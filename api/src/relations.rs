@@ -0,0 +1,169 @@
+use infrastructure::models::{Game, GameInvite, Round};
+use shared::{
+    game_status::GameStatus,
+    request::RequestEvent,
+    response::{GameState, Relation},
+};
+
+// Collects the follow-up links of a resource. Realtime actions are advertised with the
+// `WS` method and the event name as resource, since they go through the game stream.
+#[derive(Default)]
+pub struct RelationBuilder {
+    relations: Vec<Relation>,
+}
+
+impl RelationBuilder {
+    pub fn new() -> RelationBuilder {
+        RelationBuilder::default()
+    }
+
+    pub fn link(mut self, route: String, method: &str, resource: &str) -> RelationBuilder {
+        self.relations.push(Relation {
+            route,
+            method: method.to_string(),
+            resource: resource.to_string(),
+        });
+        self
+    }
+
+    pub fn event(self, game_id: i32, event: RequestEvent) -> RelationBuilder {
+        self.link(format!("/game/{}", game_id), "WS", &event.to_string())
+    }
+
+    pub fn build(self) -> Vec<Relation> {
+        self.relations
+    }
+}
+
+pub fn game(game: &Game) -> Vec<Relation> {
+    game_links(game.id, game.status.clone())
+}
+
+pub fn game_state(state: &GameState) -> Vec<Relation> {
+    game_links(state.game_id, Some(state.game_status.clone()))
+}
+
+fn game_links(game_id: i32, status: Option<String>) -> Vec<Relation> {
+    let builder = RelationBuilder::new()
+        .link(format!("/games/{}", game_id), "GET", "game")
+        .link(format!("/games/{}/state", game_id), "GET", "game_state");
+
+    // Players are only listed while the game is open or running.
+    match status.map(GameStatus::from_string) {
+        Some(GameStatus::PLAYING) => builder
+            .link(format!("/games/{}/players", game_id), "GET", "players")
+            .link(format!("/games/{}/rounds/current", game_id), "GET", "round")
+            .link(format!("/games/{}/stats", game_id), "GET", "stats")
+            .event(game_id, RequestEvent::PauseGame)
+            .event(game_id, RequestEvent::LeaveGame)
+            .build(),
+        Some(GameStatus::PAUSED) => builder
+            .link(format!("/games/{}/players", game_id), "GET", "players")
+            .link(format!("/games/{}/rounds/current", game_id), "GET", "round")
+            .link(format!("/games/{}/stats", game_id), "GET", "stats")
+            .event(game_id, RequestEvent::ResumeGame)
+            .event(game_id, RequestEvent::LeaveGame)
+            .build(),
+        Some(GameStatus::FINISHED) => builder
+            .link(format!("/games/{}/stats", game_id), "GET", "stats")
+            .link(format!("/games/{}/rematch", game_id), "POST", "rematch")
+            .event(game_id, RequestEvent::RequestRematch)
+            .build(),
+        Some(GameStatus::ABANDONED) => builder
+            .link(format!("/games/{}/stats", game_id), "GET", "stats")
+            .build(),
+        _ => builder
+            .link(format!("/games/{}/players", game_id), "GET", "players")
+            .event(game_id, RequestEvent::JoinGame)
+            .event(game_id, RequestEvent::LeaveGame)
+            .event(game_id, RequestEvent::SetReady)
+            .event(game_id, RequestEvent::UnsetReady)
+            .event(game_id, RequestEvent::StartGame)
+            .build(),
+    }
+}
+
+pub fn invite(invite: &GameInvite) -> Vec<Relation> {
+    RelationBuilder::new()
+        .link(format!("/games/{}", invite.game_id), "GET", "game")
+        .link(
+            format!("/games/{}/invites/{}", invite.game_id, invite.code),
+            "DELETE",
+            "invite",
+        )
+        .event(invite.game_id, RequestEvent::JoinGame)
+        .build()
+}
+
+pub fn matchmaking() -> Vec<Relation> {
+    RelationBuilder::new()
+        .link(String::from("/matchmaking/queue"), "DELETE", "queue")
+        .link(String::from("/matchmaking"), "WS", "match_found")
+        .link(String::from("/matchmaking/match"), "GET", "match")
+        .build()
+}
+
+pub fn match_found(game_id: i32) -> Vec<Relation> {
+    RelationBuilder::new()
+        .link(format!("/games/{}", game_id), "GET", "game")
+        .event(game_id, RequestEvent::JoinGame)
+        .build()
+}
+
+pub fn players(game_id: i32) -> Vec<Relation> {
+    RelationBuilder::new()
+        .link(format!("/games/{}", game_id), "GET", "game")
+        .link(format!("/games/{}/stats", game_id), "GET", "stats")
+        .build()
+}
+
+pub fn player(game_id: i32, player_id: &str) -> Vec<Relation> {
+    RelationBuilder::new()
+        .link(format!("/games/{}", game_id), "GET", "game")
+        .link(
+            format!("/games/{}/players/{}/stats", game_id, player_id),
+            "GET",
+            "stats",
+        )
+        .build()
+}
+
+// `game_rounds` is the number of rounds the game is played over.
+pub fn round(round: &Round, game_status: Option<String>, game_rounds: i32) -> Vec<Relation> {
+    let mut builder = RelationBuilder::new()
+        .link(format!("/games/{}", round.game_id), "GET", "game")
+        .link(
+            format!("/games/{}/rounds/{}/stats", round.game_id, round.id),
+            "GET",
+            "stats",
+        );
+
+    let status = game_status.map(GameStatus::from_string);
+    let playing = matches!(status, Some(GameStatus::PLAYING));
+    let running = playing || matches!(status, Some(GameStatus::PAUSED));
+    match round.end_time {
+        Some(_) => {
+            if playing && round.round_number < game_rounds {
+                builder = builder.event(round.game_id, RequestEvent::StartNextRound);
+            }
+            if running {
+                builder = builder.event(round.game_id, RequestEvent::FinishGame);
+            }
+        }
+        None => {
+            if playing {
+                builder = builder
+                    .event(round.game_id, RequestEvent::FinishRound)
+                    .event(round.game_id, RequestEvent::SendRoundResult);
+            }
+        }
+    }
+    builder.build()
+}
+
+// The players of a finished game are no longer listed, so stats only lead back to the game.
+pub fn stats(game_id: i32) -> Vec<Relation> {
+    RelationBuilder::new()
+        .link(format!("/games/{}", game_id), "GET", "game")
+        .build()
+}
@@ -0,0 +1,73 @@
+use rocket::{
+    http::Status,
+    response::{content, status},
+    tokio::task,
+};
+use serde::Serialize;
+use shared::{
+    error::{Error, ErrorKind},
+    response::{Relation, Response},
+};
+
+pub type JsonResponse = status::Custom<content::RawJson<String>>;
+
+// Services talk to Postgres and Redis synchronously, so their calls run on the blocking
+// pool instead of holding up the async workers.
+pub async fn blocking<T, F>(call: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    match task::spawn_blocking(call).await {
+        Ok(result) => result,
+        Err(e) => Err(Error::with_cause(
+            ErrorKind::Internal,
+            String::from("Internal server error"),
+            e,
+        )),
+    }
+}
+
+// Wraps a service result in the `Response` envelope; errors keep the status the service
+// picked for them.
+pub fn respond<T: Serialize>(
+    status: Status,
+    result: Result<T, Error>,
+    relations: Vec<Relation>,
+) -> JsonResponse {
+    match result {
+        Ok(data) => json(
+            status,
+            &Response {
+                is_ok: true,
+                data: Some(data),
+                relations,
+            },
+        ),
+        Err(e) => {
+            if e.cause.is_some() {
+                println!("Request failed: {}", e);
+            }
+            json(
+                e.status_code,
+                &Response {
+                    is_ok: false,
+                    data: Some(e),
+                    relations: Vec::new(),
+                },
+            )
+        }
+    }
+}
+
+fn json<T: Serialize>(status: Status, body: &T) -> JsonResponse {
+    match serde_json::to_string(body) {
+        Ok(body) => status::Custom(status, content::RawJson(body)),
+        Err(_) => status::Custom(
+            Status::InternalServerError,
+            content::RawJson(String::from(
+                r#"{"is_ok":false,"data":{"kind":"INTERNAL","code":5003,"message":"Internal server error","status_code":500},"relations":[]}"#,
+            )),
+        ),
+    }
+}
@@ -0,0 +1,74 @@
+use crate::hub::GameHub;
+use infrastructure::{models::Round, Pools};
+use rocket::tokio::{self, task, time};
+use service::game_state_service;
+use shared::response::{RoundFinished, ServerMessage};
+
+#[derive(Clone)]
+pub struct RoundScheduler {
+    hub: GameHub,
+    pools: Pools,
+}
+
+impl RoundScheduler {
+    pub fn new(hub: GameHub, pools: Pools) -> RoundScheduler {
+        RoundScheduler { hub, pools }
+    }
+
+    pub fn schedule(&self, game_id: i32, round_id: i32) {
+        let hub = self.hub.clone();
+        let pools = self.pools.clone();
+        tokio::spawn(async move {
+            // Pausing moves the deadline, so it is checked again after waking up. A paused
+            // round has none and is scheduled again once the game is resumed.
+            loop {
+                let deadline_pools = pools.clone();
+                let deadline = match task::spawn_blocking(move || {
+                    game_state_service::get_round_deadline(&deadline_pools, round_id)
+                })
+                .await
+                {
+                    Ok(Ok(Some(deadline))) => deadline,
+                    _ => return,
+                };
+
+                let remaining = deadline - chrono::Utc::now().naive_utc();
+                match remaining.to_std() {
+                    Ok(remaining) if !remaining.is_zero() => time::sleep(remaining).await,
+                    _ => break,
+                }
+            }
+
+            if let Ok(Ok(Some(round))) =
+                task::spawn_blocking(move || game_state_service::expire_round(&pools, round_id))
+                    .await
+            {
+                hub.publish(game_id, ServerMessage::RoundFinished(round_finished(round)));
+            }
+        });
+    }
+
+    pub async fn resume_open_rounds(&self) {
+        let pools = self.pools.clone();
+        match task::spawn_blocking(move || game_state_service::get_open_rounds(&pools)).await {
+            Ok(Ok(rounds)) => {
+                for round in rounds {
+                    self.schedule(round.game_id, round.id);
+                }
+            }
+            _ => println!("Could not resume timers of open rounds"),
+        }
+    }
+}
+
+pub fn round_finished(round: Round) -> RoundFinished {
+    RoundFinished {
+        round_number: round.round_number,
+        topic: round.topic,
+        start_time: round.start_time.and_utc().timestamp_millis(),
+        end_time: round
+            .end_time
+            .map(|end_time| end_time.and_utc().timestamp_millis())
+            .unwrap_or_default(),
+    }
+}
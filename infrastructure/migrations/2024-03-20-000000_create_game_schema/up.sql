@@ -0,0 +1,134 @@
+CREATE TABLE IF NOT EXISTS users (
+    clerk_id VARCHAR PRIMARY KEY,
+    biography VARCHAR(1000),
+    tag VARCHAR NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS friends (
+    id SERIAL PRIMARY KEY,
+    user_id VARCHAR NOT NULL REFERENCES users (clerk_id) ON DELETE CASCADE,
+    friend_id VARCHAR NOT NULL REFERENCES users (clerk_id) ON DELETE CASCADE,
+    created_at VARCHAR NOT NULL,
+    UNIQUE (user_id, friend_id)
+);
+
+CREATE INDEX IF NOT EXISTS friends_friend_id_idx ON friends (friend_id);
+
+CREATE TABLE IF NOT EXISTS games (
+    id SERIAL PRIMARY KEY,
+    status VARCHAR DEFAULT 'waiting',
+    game_slug VARCHAR NOT NULL UNIQUE,
+    max_players INTEGER NOT NULL,
+    rounds INTEGER NOT NULL,
+    winner_id VARCHAR REFERENCES users (clerk_id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS games_status_idx ON games (status);
+
+SELECT diesel_manage_updated_at('games')
+WHERE NOT EXISTS (
+    SELECT 1 FROM pg_trigger WHERE tgname = 'set_updated_at' AND tgrelid = 'games'::regclass
+);
+
+CREATE TABLE IF NOT EXISTS players (
+    id SERIAL PRIMARY KEY,
+    player_id VARCHAR NOT NULL REFERENCES users (clerk_id) ON DELETE CASCADE,
+    game_id INTEGER NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    is_host BOOLEAN DEFAULT FALSE,
+    left_game_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (player_id, game_id)
+);
+
+CREATE INDEX IF NOT EXISTS players_game_id_idx ON players (game_id);
+
+SELECT diesel_manage_updated_at('players')
+WHERE NOT EXISTS (
+    SELECT 1 FROM pg_trigger WHERE tgname = 'set_updated_at' AND tgrelid = 'players'::regclass
+);
+
+CREATE TABLE IF NOT EXISTS rounds (
+    id SERIAL PRIMARY KEY,
+    game_id INTEGER NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    round_number INTEGER NOT NULL,
+    topic VARCHAR(100) NOT NULL,
+    start_time TIMESTAMP NOT NULL,
+    end_time TIMESTAMP,
+    UNIQUE (game_id, round_number)
+);
+
+CREATE INDEX IF NOT EXISTS rounds_open_idx ON rounds (game_id) WHERE end_time IS NULL;
+
+CREATE TABLE IF NOT EXISTS player_scoring (
+    id SERIAL PRIMARY KEY,
+    player_id VARCHAR NOT NULL REFERENCES users (clerk_id) ON DELETE CASCADE,
+    total_xp INTEGER,
+    highest_score_game INTEGER,
+    highest_score_round INTEGER,
+    games_played INTEGER,
+    games_won INTEGER,
+    games_lost INTEGER,
+    games_top3 INTEGER,
+    games_bottom3 INTEGER,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+SELECT diesel_manage_updated_at('player_scoring')
+WHERE NOT EXISTS (
+    SELECT 1 FROM pg_trigger WHERE tgname = 'set_updated_at' AND tgrelid = 'player_scoring'::regclass
+);
+
+CREATE TABLE IF NOT EXISTS player_scoring_round (
+    id SERIAL PRIMARY KEY,
+    player_id VARCHAR NOT NULL REFERENCES users (clerk_id) ON DELETE CASCADE,
+    game_id INTEGER NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    score INTEGER,
+    round_id INTEGER NOT NULL REFERENCES rounds (id) ON DELETE CASCADE,
+    place INTEGER,
+    is_winner BOOLEAN,
+    time_used_to_complete BIGINT,
+    first_topic VARCHAR(100),
+    second_topic VARCHAR(100),
+    third_topic VARCHAR(100),
+    has_stopped_game BOOLEAN,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (round_id, player_id)
+);
+
+CREATE INDEX IF NOT EXISTS player_scoring_round_game_player_idx ON player_scoring_round (game_id, player_id);
+
+SELECT diesel_manage_updated_at('player_scoring_round')
+WHERE NOT EXISTS (
+    SELECT 1 FROM pg_trigger WHERE tgname = 'set_updated_at' AND tgrelid = 'player_scoring_round'::regclass
+);
+
+CREATE TABLE IF NOT EXISTS demo (
+    id SERIAL PRIMARY KEY,
+    clerk_id VARCHAR NOT NULL,
+    drawing JSONB,
+    term INTEGER,
+    guess INTEGER,
+    term_confidence NUMERIC,
+    host BOOLEAN NOT NULL DEFAULT FALSE,
+    image_url TEXT,
+    username TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS demo_clerk_id_idx ON demo (clerk_id);
+
+SELECT diesel_manage_updated_at('demo')
+WHERE NOT EXISTS (
+    SELECT 1 FROM pg_trigger WHERE tgname = 'set_updated_at' AND tgrelid = 'demo'::regclass
+);
+
+CREATE TABLE IF NOT EXISTS training (
+    clerk_id VARCHAR PRIMARY KEY,
+    drawing JSONB NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE games ADD COLUMN round_duration INTEGER NOT NULL DEFAULT 60;
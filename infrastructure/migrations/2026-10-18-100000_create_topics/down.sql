@@ -0,0 +1,4 @@
+ALTER TABLE games DROP COLUMN topic_difficulty;
+ALTER TABLE games DROP COLUMN topic_category;
+
+DROP TABLE topics;
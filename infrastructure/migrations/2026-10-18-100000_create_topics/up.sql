@@ -0,0 +1,12 @@
+CREATE TABLE topics (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(100) NOT NULL UNIQUE,
+    category VARCHAR(50) NOT NULL,
+    difficulty VARCHAR(20) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX topics_category_difficulty_idx ON topics (category, difficulty);
+
+ALTER TABLE games ADD COLUMN topic_category VARCHAR(50);
+ALTER TABLE games ADD COLUMN topic_difficulty VARCHAR(20);
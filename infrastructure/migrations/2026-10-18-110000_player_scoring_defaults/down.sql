@@ -0,0 +1,19 @@
+ALTER TABLE player_scoring DROP CONSTRAINT player_scoring_player_id_key;
+
+ALTER TABLE player_scoring
+    ALTER COLUMN total_xp DROP NOT NULL,
+    ALTER COLUMN total_xp DROP DEFAULT,
+    ALTER COLUMN highest_score_game DROP NOT NULL,
+    ALTER COLUMN highest_score_game DROP DEFAULT,
+    ALTER COLUMN highest_score_round DROP NOT NULL,
+    ALTER COLUMN highest_score_round DROP DEFAULT,
+    ALTER COLUMN games_played DROP NOT NULL,
+    ALTER COLUMN games_played DROP DEFAULT,
+    ALTER COLUMN games_won DROP NOT NULL,
+    ALTER COLUMN games_won DROP DEFAULT,
+    ALTER COLUMN games_lost DROP NOT NULL,
+    ALTER COLUMN games_lost DROP DEFAULT,
+    ALTER COLUMN games_top3 DROP NOT NULL,
+    ALTER COLUMN games_top3 DROP DEFAULT,
+    ALTER COLUMN games_bottom3 DROP NOT NULL,
+    ALTER COLUMN games_bottom3 DROP DEFAULT;
@@ -0,0 +1,29 @@
+UPDATE player_scoring SET
+    total_xp = COALESCE(total_xp, 0),
+    highest_score_game = COALESCE(highest_score_game, 0),
+    highest_score_round = COALESCE(highest_score_round, 0),
+    games_played = COALESCE(games_played, 0),
+    games_won = COALESCE(games_won, 0),
+    games_lost = COALESCE(games_lost, 0),
+    games_top3 = COALESCE(games_top3, 0),
+    games_bottom3 = COALESCE(games_bottom3, 0);
+
+ALTER TABLE player_scoring
+    ALTER COLUMN total_xp SET DEFAULT 0,
+    ALTER COLUMN total_xp SET NOT NULL,
+    ALTER COLUMN highest_score_game SET DEFAULT 0,
+    ALTER COLUMN highest_score_game SET NOT NULL,
+    ALTER COLUMN highest_score_round SET DEFAULT 0,
+    ALTER COLUMN highest_score_round SET NOT NULL,
+    ALTER COLUMN games_played SET DEFAULT 0,
+    ALTER COLUMN games_played SET NOT NULL,
+    ALTER COLUMN games_won SET DEFAULT 0,
+    ALTER COLUMN games_won SET NOT NULL,
+    ALTER COLUMN games_lost SET DEFAULT 0,
+    ALTER COLUMN games_lost SET NOT NULL,
+    ALTER COLUMN games_top3 SET DEFAULT 0,
+    ALTER COLUMN games_top3 SET NOT NULL,
+    ALTER COLUMN games_bottom3 SET DEFAULT 0,
+    ALTER COLUMN games_bottom3 SET NOT NULL;
+
+ALTER TABLE player_scoring ADD CONSTRAINT player_scoring_player_id_key UNIQUE (player_id);
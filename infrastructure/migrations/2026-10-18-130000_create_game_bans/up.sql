@@ -0,0 +1,8 @@
+CREATE TABLE game_bans (
+    id SERIAL PRIMARY KEY,
+    game_id INTEGER NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    player_id VARCHAR NOT NULL REFERENCES users (clerk_id) ON DELETE CASCADE,
+    banned_by VARCHAR NOT NULL REFERENCES users (clerk_id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (game_id, player_id)
+);
@@ -0,0 +1,2 @@
+DROP TABLE game_invites;
+ALTER TABLE games DROP COLUMN password_hash;
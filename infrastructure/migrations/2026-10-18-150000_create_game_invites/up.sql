@@ -0,0 +1,12 @@
+ALTER TABLE games ADD COLUMN password_hash VARCHAR;
+
+CREATE TABLE game_invites (
+    id SERIAL PRIMARY KEY,
+    game_id INTEGER NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    code VARCHAR(12) NOT NULL UNIQUE,
+    created_by VARCHAR NOT NULL REFERENCES users (clerk_id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX game_invites_game_id_idx ON game_invites (game_id);
@@ -0,0 +1,2 @@
+DROP INDEX games_previous_game_id_idx;
+ALTER TABLE games DROP COLUMN previous_game_id;
@@ -0,0 +1,3 @@
+ALTER TABLE games ADD COLUMN previous_game_id INTEGER REFERENCES games (id) ON DELETE SET NULL;
+
+CREATE UNIQUE INDEX games_previous_game_id_idx ON games (previous_game_id);
@@ -0,0 +1,2 @@
+ALTER TABLE rounds DROP COLUMN paused_time;
+ALTER TABLE rounds DROP COLUMN remaining_time;
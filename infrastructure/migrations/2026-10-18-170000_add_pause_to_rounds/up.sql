@@ -0,0 +1,2 @@
+ALTER TABLE rounds ADD COLUMN remaining_time BIGINT;
+ALTER TABLE rounds ADD COLUMN paused_time BIGINT NOT NULL DEFAULT 0;
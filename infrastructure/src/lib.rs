@@ -1,20 +1,138 @@
-use diesel::{Connection, PgConnection};
+use diesel::{
+    r2d2::{ConnectionManager, Pool, PooledConnection},
+    PgConnection,
+};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use dotenvy::dotenv;
-use redis::{Client, Connection as RedisConnection};
-use std::env;
+use redis::Client;
+use shared::error::{Error, ErrorKind};
+use std::{env, time::Duration};
 
 pub mod models;
 pub mod schema;
 
-pub fn establish_connection() -> PgConnection {
-    dotenv().ok();
-    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgConnection::establish(&db_url).expect(&format!("Error connecting to {}", db_url))
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+pub type DbConnection = PooledConnection<ConnectionManager<PgConnection>>;
+pub type RedisConnection = r2d2::PooledConnection<Client>;
+
+#[derive(Clone)]
+pub struct Pools {
+    db: Pool<ConnectionManager<PgConnection>>,
+    redis: r2d2::Pool<Client>,
+}
+
+impl Pools {
+    // Pool sizes and the time to wait for a free connection can be tuned with
+    // DATABASE_POOL_SIZE, REDIS_POOL_SIZE and POOL_TIMEOUT_SECONDS.
+    pub fn from_env() -> Result<Pools, Error> {
+        dotenv().ok();
+        let db_url = env_var("DATABASE_URL")?;
+        let redis_url = env_var("REDIS_URL")?;
+        let db_pool_size = env_number("DATABASE_POOL_SIZE", 10)?;
+        let redis_pool_size = env_number("REDIS_POOL_SIZE", 10)?;
+        let timeout = Duration::from_secs(env_number("POOL_TIMEOUT_SECONDS", 5)? as u64);
+
+        let db = match Pool::builder()
+            .max_size(db_pool_size)
+            .connection_timeout(timeout)
+            .test_on_check_out(true)
+            .build(ConnectionManager::<PgConnection>::new(db_url))
+        {
+            Ok(pool) => pool,
+            Err(e) => {
+                return Err(Error::with_cause(
+                    ErrorKind::Unavailable,
+                    String::from("Error connecting to the database"),
+                    e,
+                ))
+            }
+        };
+
+        let client = match Client::open(redis_url) {
+            Ok(client) => client,
+            Err(e) => {
+                return Err(Error::with_cause(
+                    ErrorKind::Configuration,
+                    String::from("Invalid REDIS_URL"),
+                    e,
+                ))
+            }
+        };
+
+        let redis = match r2d2::Pool::builder()
+            .max_size(redis_pool_size)
+            .connection_timeout(timeout)
+            .test_on_check_out(true)
+            .build(client)
+        {
+            Ok(pool) => pool,
+            Err(e) => {
+                return Err(Error::with_cause(
+                    ErrorKind::Unavailable,
+                    String::from("Error connecting to Redis"),
+                    e,
+                ))
+            }
+        };
+
+        Ok(Pools { db, redis })
+    }
+
+    pub fn db(&self) -> Result<DbConnection, Error> {
+        match self.db.get() {
+            Ok(conn) => Ok(conn),
+            Err(e) => Err(Error::with_cause(
+                ErrorKind::Unavailable,
+                String::from("Database unavailable"),
+                e,
+            )),
+        }
+    }
+
+    pub fn run_pending_migrations(&self) -> Result<(), Error> {
+        let mut conn = self.db()?;
+        match conn.run_pending_migrations(MIGRATIONS) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::with_cause(
+                ErrorKind::Storage,
+                String::from("Error running database migrations"),
+                e,
+            )),
+        }
+    }
+
+    pub fn redis(&self) -> Result<RedisConnection, Error> {
+        match self.redis.get() {
+            Ok(conn) => Ok(conn),
+            Err(e) => Err(Error::with_cause(
+                ErrorKind::Unavailable,
+                String::from("Redis unavailable"),
+                e,
+            )),
+        }
+    }
+}
+
+fn env_var(name: &str) -> Result<String, Error> {
+    match env::var(name) {
+        Ok(value) => Ok(value),
+        Err(_) => Err(Error::new(
+            ErrorKind::Configuration,
+            format!("{} must be set", name),
+        )),
+    }
 }
 
-pub fn establish_redis_connection() -> RedisConnection {
-    dotenv().ok();
-    let redis_url = env::var("REDIS_URL").expect("REDIS_URL must be set");
-    let client = Client::open(redis_url).expect("Error connecting to Redis");
-    client.get_connection().expect("Error connecting to Redis")
+fn env_number(name: &str, default: u32) -> Result<u32, Error> {
+    match env::var(name) {
+        Ok(value) => match value.parse::<u32>() {
+            Ok(number) => Ok(number),
+            Err(_) => Err(Error::new(
+                ErrorKind::Configuration,
+                format!("{} must be a number", name),
+            )),
+        },
+        Err(_) => Ok(default),
+    }
 }
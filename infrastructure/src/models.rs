@@ -12,10 +12,35 @@ pub struct Game {
     pub game_slug: String,
     pub max_players: i32,
     pub rounds: i32,
+    pub round_duration: i32,
+    pub topic_category: Option<String>,
+    pub topic_difficulty: Option<String>,
+    pub visibility: String,
+    pub min_players: Option<i32>,
+    #[serde(skip_serializing)]
+    pub password_hash: Option<String>,
+    pub previous_game_id: Option<i32>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
 
+#[derive(Insertable, Clone, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::games)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewGame {
+    pub status: String,
+    pub game_slug: String,
+    pub max_players: i32,
+    pub rounds: i32,
+    pub round_duration: i32,
+    pub visibility: String,
+    pub min_players: Option<i32>,
+    pub password_hash: Option<String>,
+    pub previous_game_id: Option<i32>,
+    pub topic_category: Option<String>,
+    pub topic_difficulty: Option<String>,
+}
+
 #[derive(AsChangeset, Serialize, Deserialize)]
 #[diesel(table_name = crate::schema::games)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -46,6 +71,36 @@ pub struct NewPlayer {
     pub is_host: Option<bool>,
 }
 
+#[derive(Insertable, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::game_bans)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewGameBan {
+    pub game_id: i32,
+    pub player_id: String,
+    pub banned_by: String,
+}
+
+#[derive(Queryable, Selectable, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::game_invites)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct GameInvite {
+    pub id: i32,
+    pub game_id: i32,
+    pub code: String,
+    pub created_by: String,
+    pub created_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::game_invites)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewGameInvite {
+    pub game_id: i32,
+    pub code: String,
+    pub created_by: String,
+}
+
 #[derive(AsChangeset, Serialize, Deserialize)]
 #[diesel(table_name = crate::schema::players)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -57,7 +112,7 @@ pub struct UpdatePlayer {
     pub left_game_at: Option<NaiveDateTime>,
 }
 
-#[derive(Queryable, Serialize, Deserialize)]
+#[derive(Queryable, Selectable, Serialize, Deserialize)]
 #[diesel(table_name = crate::schema::player_scoring)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 #[diesel(primary_key(player_id))]
@@ -75,6 +130,21 @@ pub struct PlayerScoring {
     pub updated_at: NaiveDateTime,
 }
 
+#[derive(Insertable, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::player_scoring)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewPlayerScoring {
+    pub player_id: String,
+    pub total_xp: i32,
+    pub highest_score_game: i32,
+    pub highest_score_round: i32,
+    pub games_played: i32,
+    pub games_won: i32,
+    pub games_lost: i32,
+    pub games_top3: i32,
+    pub games_bottom3: i32,
+}
+
 #[derive(AsChangeset, Serialize, Deserialize)]
 #[diesel(table_name = crate::schema::player_scoring)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -101,6 +171,8 @@ pub struct Round {
     pub topic: String,
     pub start_time: NaiveDateTime,
     pub end_time: Option<NaiveDateTime>,
+    pub remaining_time: Option<i64>,
+    pub paused_time: i64,
 }
 
 #[derive(Insertable, Serialize, Deserialize)]
@@ -164,3 +236,23 @@ pub struct PlayerScoringRound {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
+
+#[derive(Queryable, Selectable, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::topics)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Topic {
+    pub id: i32,
+    pub name: String,
+    pub category: String,
+    pub difficulty: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::topics)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewTopic {
+    pub name: String,
+    pub category: String,
+    pub difficulty: String,
+}
@@ -25,6 +25,28 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    game_bans (id) {
+        id -> Integer,
+        game_id -> Integer,
+        player_id -> Varchar,
+        banned_by -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    game_invites (id) {
+        id -> Integer,
+        game_id -> Integer,
+        #[max_length = 12]
+        code -> Varchar,
+        created_by -> Varchar,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     games (id) {
         id -> Integer,
@@ -35,6 +57,16 @@ diesel::table! {
         winner_id -> Nullable<Varchar>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        round_duration -> Integer,
+        #[max_length = 50]
+        topic_category -> Nullable<Varchar>,
+        #[max_length = 20]
+        topic_difficulty -> Nullable<Varchar>,
+        #[max_length = 20]
+        visibility -> Varchar,
+        min_players -> Nullable<Integer>,
+        password_hash -> Nullable<Varchar>,
+        previous_game_id -> Nullable<Integer>,
     }
 }
 
@@ -42,14 +74,14 @@ diesel::table! {
     player_scoring (id) {
         id -> Integer,
         player_id -> Varchar,
-        total_xp -> Nullable<Integer>,
-        highest_score_game -> Nullable<Integer>,
-        highest_score_round -> Nullable<Integer>,
-        games_played -> Nullable<Integer>,
-        games_won -> Nullable<Integer>,
-        games_lost -> Nullable<Integer>,
-        games_top3 -> Nullable<Integer>,
-        games_bottom3 -> Nullable<Integer>,
+        total_xp -> Integer,
+        highest_score_game -> Integer,
+        highest_score_round -> Integer,
+        games_played -> Integer,
+        games_won -> Integer,
+        games_lost -> Integer,
+        games_top3 -> Integer,
+        games_bottom3 -> Integer,
         created_at -> Timestamp,
         updated_at -> Timestamp,
     }
@@ -98,6 +130,21 @@ diesel::table! {
         topic -> Varchar,
         start_time -> Timestamp,
         end_time -> Nullable<Timestamp>,
+        remaining_time -> Nullable<BigInt>,
+        paused_time -> BigInt,
+    }
+}
+
+diesel::table! {
+    topics (id) {
+        id -> Integer,
+        #[max_length = 100]
+        name -> Varchar,
+        #[max_length = 50]
+        category -> Varchar,
+        #[max_length = 20]
+        difficulty -> Varchar,
+        created_at -> Timestamp,
     }
 }
 
@@ -117,16 +164,28 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(game_bans -> games (game_id));
+diesel::joinable!(game_invites -> games (game_id));
+diesel::joinable!(games -> users (winner_id));
+diesel::joinable!(player_scoring -> users (player_id));
+diesel::joinable!(player_scoring_round -> games (game_id));
+diesel::joinable!(player_scoring_round -> rounds (round_id));
+diesel::joinable!(player_scoring_round -> users (player_id));
+diesel::joinable!(players -> games (game_id));
 diesel::joinable!(players -> users (player_id));
+diesel::joinable!(rounds -> games (game_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     demo,
     friends,
+    game_bans,
+    game_invites,
     games,
     player_scoring,
     player_scoring_round,
     players,
     rounds,
+    topics,
     training,
     users,
 );
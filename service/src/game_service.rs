@@ -1,16 +1,48 @@
+use crate::game_state_service::finalize_game;
+use crate::invite_service::hash_password;
+use crate::topic_service::check_topics;
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel::result::Error as DieselError;
+use diesel::{define_sql_function, sql_types::Text};
 use infrastructure::{
-    establish_connection, establish_redis_connection,
-    models::{Game, UpdateGame},
-    schema::games,
+    models::{Game, NewGame, NewPlayer, Player},
+    schema::{games, players},
+    Pools,
 };
+use rand::{seq::SliceRandom, Rng};
 use redis::Commands;
-use rocket::http::Status;
-use shared::{error::Error, game_status::GameStatus, RequestOptions};
+use shared::{
+    error::{Error, ErrorKind},
+    game_status::GameStatus,
+    game_visibility::GameVisibility,
+    request::CreateGame,
+    topic::TopicDifficulty,
+    RequestOptions,
+};
+
+const SLUG_ADJECTIVES: [&str; 16] = [
+    "brave", "calm", "clever", "cosy", "eager", "fancy", "gentle", "happy", "jolly", "lucky",
+    "mighty", "proud", "quick", "shiny", "silly", "witty",
+];
+const SLUG_NOUNS: [&str; 16] = [
+    "badger", "brush", "canvas", "crayon", "easel", "falcon", "otter", "palette", "panda",
+    "pencil", "penguin", "sketch", "sparrow", "tiger", "walrus", "whale",
+];
+const SLUG_ATTEMPTS: usize = 5;
+
+define_sql_function!(fn nextval(sequence: Text) -> BigInt);
+
+pub struct Rematch {
+    pub accepted: usize,
+    pub needed: usize,
+    pub game: Option<Game>,
+    // Only the request that created the rematch has this set.
+    pub created: bool,
+}
 
-pub fn get_game(id: Option<i32>, slug: Option<String>) -> Result<Game, Error> {
-    let mut conn = establish_connection();
+pub fn get_game(pools: &Pools, id: Option<i32>, slug: Option<String>) -> Result<Game, Error> {
+    let mut conn = pools.db()?;
     match id {
         Some(id) => {
             let game: Result<Game, DieselError> = games::table
@@ -19,13 +51,11 @@ pub fn get_game(id: Option<i32>, slug: Option<String>) -> Result<Game, Error> {
                 .first::<Game>(&mut conn);
             match game {
                 Ok(game) => Ok(game),
-                Err(diesel::NotFound) => {
-                    Err(Error::new(String::from("Game not found"), Status::NotFound))
-                }
-                Err(_) => Err(Error::new(
-                    String::from("Internal server error"),
-                    Status::InternalServerError,
+                Err(diesel::NotFound) => Err(Error::new(
+                    ErrorKind::GameNotFound,
+                    String::from("Game not found"),
                 )),
+                Err(e) => Err(Error::storage(e)),
             }
         }
         None => match slug {
@@ -36,25 +66,23 @@ pub fn get_game(id: Option<i32>, slug: Option<String>) -> Result<Game, Error> {
                     .first::<Game>(&mut conn);
                 match game {
                     Ok(game) => Ok(game),
-                    Err(diesel::NotFound) => {
-                        Err(Error::new(String::from("Game not found"), Status::NotFound))
-                    }
-                    Err(_) => Err(Error::new(
-                        String::from("Internal server error"),
-                        Status::InternalServerError,
+                    Err(diesel::NotFound) => Err(Error::new(
+                        ErrorKind::GameNotFound,
+                        String::from("Game not found"),
                     )),
+                    Err(e) => Err(Error::storage(e)),
                 }
             }
             None => Err(Error::new(
+                ErrorKind::InvalidRequest,
                 String::from("Invalid request"),
-                Status::BadRequest,
             )),
         },
     }
 }
 
-pub fn finish_game(id: i32) -> Result<(), Error> {
-    let mut conn = establish_connection();
+pub fn finish_game(pools: &Pools, id: i32) -> Result<(), Error> {
+    let mut conn = pools.db()?;
     let game: Result<Game, DieselError> = games::table
         .select(Game::as_select())
         .find(id)
@@ -64,46 +92,29 @@ pub fn finish_game(id: i32) -> Result<(), Error> {
             if game.status == Some(GameStatus::to_string(GameStatus::FINISHED)) {
                 Ok(())
             } else {
-                let updated_game = UpdateGame {
-                    status: GameStatus::to_string(GameStatus::FINISHED),
-                    winner_id: None,
-                };
-                let result = diesel::update(games::table.find(id))
-                    .set(updated_game)
-                    .execute(&mut conn);
-                match result {
-                    Ok(_) => Ok(()),
-                    Err(_) => Err(Error::new(
-                        String::from("Internal server error"),
-                        Status::InternalServerError,
-                    )),
-                }
+                finalize_game(&mut conn, id)
             }
         }
-        Err(diesel::NotFound) => Err(Error::new(String::from("Game not found"), Status::NotFound)),
-        Err(_) => Err(Error::new(
-            String::from("Internal server error"),
-            Status::InternalServerError,
+        Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::GameNotFound,
+            String::from("Game not found"),
         )),
+        Err(e) => Err(Error::storage(e)),
     }
 }
 
-pub fn get_active_games(options: Option<RequestOptions>) -> Result<Vec<Game>, Error> {
-    let mut redis_conn = establish_redis_connection();
-    let mut conn = establish_connection();
+pub fn get_active_games(
+    pools: &Pools,
+    options: Option<RequestOptions>,
+) -> Result<Vec<Game>, Error> {
+    let mut redis_conn = pools.redis()?;
+    let mut conn = pools.db()?;
 
     let active_games_ids = match redis_conn.smembers::<&str, Vec<i32>>("active_games") {
         Ok(active_games) => active_games,
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
-            ))
-        }
+        Err(e) => return Err(Error::storage(e)),
     };
 
-    let mut active_games = Vec::new();
-
     let opts = match options {
         Some(opts) => opts,
         None => RequestOptions {
@@ -112,27 +123,367 @@ pub fn get_active_games(options: Option<RequestOptions>) -> Result<Vec<Game>, Er
         },
     };
 
-    for game_id in active_games_ids {
-        if active_games.len() == opts.limit as usize {
-            break;
+    // Redis sets are unordered, so the page is cut in the database to keep it stable.
+    let active_games = games::table
+        .select(Game::as_select())
+        .filter(games::id.eq_any(active_games_ids))
+        .filter(games::status.eq_any([
+            GameStatus::to_string(GameStatus::WAITING),
+            GameStatus::to_string(GameStatus::PLAYING),
+            GameStatus::to_string(GameStatus::PAUSED),
+        ]))
+        .filter(games::visibility.eq(GameVisibility::to_string(GameVisibility::PUBLIC)))
+        .order(games::id.asc())
+        .limit(opts.limit as i64)
+        .offset(opts.offset as i64)
+        .load::<Game>(&mut conn);
+
+    match active_games {
+        Ok(active_games) => Ok(active_games),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+pub fn create_game(pools: &Pools, creator_id: String, options: CreateGame) -> Result<Game, Error> {
+    create_game_with_players(pools, vec![creator_id], options)
+}
+
+// The first player becomes the host, the others join the lobby right away.
+pub fn create_game_with_players(
+    pools: &Pools,
+    player_ids: Vec<String>,
+    options: CreateGame,
+) -> Result<Game, Error> {
+    if player_ids.len() > options.max_players as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidRequest,
+            String::from("Too many players for this game"),
+        ));
+    }
+    if !(2..=16).contains(&options.max_players) {
+        return Err(Error::new(
+            ErrorKind::InvalidRequest,
+            String::from("A game needs between 2 and 16 players"),
+        ));
+    }
+    if !(1..=20).contains(&options.rounds) {
+        return Err(Error::new(
+            ErrorKind::InvalidRequest,
+            String::from("A game needs between 1 and 20 rounds"),
+        ));
+    }
+    let round_duration = options.round_duration.unwrap_or(60);
+    if !(15..=300).contains(&round_duration) {
+        return Err(Error::new(
+            ErrorKind::InvalidRequest,
+            String::from("A round has to last between 15 and 300 seconds"),
+        ));
+    }
+    if options
+        .min_players
+        .is_some_and(|min_players| !(2..=options.max_players).contains(&min_players))
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidRequest,
+            String::from("The minimum player count has to be between 2 and the maximum"),
+        ));
+    }
+    let visibility = match options.visibility {
+        Some(visibility) => GameVisibility::from_string(visibility)?,
+        None => GameVisibility::PUBLIC,
+    };
+    let topic_difficulty = match options.topic_difficulty {
+        Some(difficulty) => Some(TopicDifficulty::to_string(TopicDifficulty::from_string(
+            difficulty,
+        )?)),
+        None => None,
+    };
+    let password_hash = match options.password {
+        Some(password) => Some(hash_password(&password)?),
+        None => None,
+    };
+
+    let new_game = NewGame {
+        status: GameStatus::to_string(GameStatus::WAITING),
+        game_slug: String::new(),
+        max_players: options.max_players,
+        rounds: options.rounds,
+        round_duration,
+        visibility: GameVisibility::to_string(visibility),
+        min_players: options.min_players,
+        password_hash,
+        previous_game_id: None,
+        topic_category: options.topic_category,
+        topic_difficulty,
+    };
+
+    let mut conn = pools.db()?;
+    check_topics(
+        &mut conn,
+        new_game.topic_category.as_deref(),
+        new_game.topic_difficulty.as_deref(),
+        new_game.rounds,
+    )?;
+    let result =
+        conn.transaction::<Game, DieselError, _>(|conn| insert_game(conn, &new_game, &player_ids));
+
+    let game = match result {
+        Ok(game) => game,
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    register_game(pools, game.id, &player_ids)?;
+    Ok(game)
+}
+
+// The rematch is created once `accept_ratio` of the players still in the finished game have
+// accepted. It takes over the settings, and the host if they accepted, and players who accept
+// afterwards are added to it right away.
+pub fn request_rematch(
+    pools: &Pools,
+    game_id: i32,
+    player_id: String,
+    accept_ratio: f64,
+) -> Result<Rematch, Error> {
+    let mut conn = pools.db()?;
+    let game: Game = match games::table
+        .select(Game::as_select())
+        .find(game_id)
+        .first::<Game>(&mut conn)
+    {
+        Ok(game) => game,
+        Err(diesel::NotFound) => {
+            return Err(Error::new(
+                ErrorKind::GameNotFound,
+                String::from("Game not found"),
+            ))
         }
-        let game = games::table
-            .select(Game::as_select())
-            .filter(games::status.eq(GameStatus::to_string(GameStatus::WAITING)))
-            .or_filter(games::status.eq(GameStatus::to_string(GameStatus::PLAYING)))
+        Err(e) => return Err(Error::storage(e)),
+    };
+    if game.status != Some(GameStatus::to_string(GameStatus::FINISHED)) {
+        return Err(Error::new(
+            ErrorKind::WrongGameStatus,
+            String::from("Only finished games can be rematched"),
+        ));
+    }
+
+    let mut remaining = match players::table
+        .select(Player::as_select())
+        .filter(players::game_id.eq(game_id))
+        .filter(players::left_game_at.is_null())
+        .order((players::created_at.asc(), players::player_id.asc()))
+        .load::<Player>(&mut conn)
+    {
+        Ok(remaining) => remaining,
+        Err(e) => return Err(Error::storage(e)),
+    };
+    if !remaining.iter().any(|player| player.player_id == player_id) {
+        return Err(Error::new(
+            ErrorKind::PlayerNotInGame,
+            String::from("Player not in game"),
+        ));
+    }
+
+    let mut redis_conn = pools.redis()?;
+    let (accepted_ids,) = match redis::pipe()
+        .sadd(format!("game:{}:rematch", game_id), player_id.clone())
+        .ignore()
+        .smembers(format!("game:{}:rematch", game_id))
+        .query::<(Vec<String>,)>(&mut *redis_conn)
+    {
+        Ok(accepted_ids) => accepted_ids,
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    // The host goes first so they keep hosting the rematch.
+    remaining.sort_by_key(|player| player.is_host != Some(true));
+    let needed =
+        ((accept_ratio * remaining.len() as f64).ceil() as usize).clamp(1, remaining.len());
+    let acceptors = remaining
+        .into_iter()
+        .map(|player| player.player_id)
+        .filter(|id| accepted_ids.contains(id))
+        .collect::<Vec<_>>();
+
+    let existing = match games::table
+        .select(Game::as_select())
+        .filter(games::previous_game_id.eq(game_id))
+        .first::<Game>(&mut conn)
+        .optional()
+    {
+        Ok(existing) => existing,
+        Err(e) => return Err(Error::storage(e)),
+    };
+    if existing.is_none() && acceptors.len() < needed {
+        return Ok(Rematch {
+            accepted: acceptors.len(),
+            needed,
+            game: None,
+            created: false,
+        });
+    }
+
+    let result = conn.transaction::<(Game, bool), DieselError, _>(|conn| {
+        // Locking the finished game keeps concurrent accepts from creating two rematches.
+        games::table
+            .select(games::id)
             .find(game_id)
-            .first::<Game>(&mut conn);
-        match game {
-            Ok(game) => active_games.push(game),
-            Err(diesel::NotFound) => continue,
-            Err(_) => {
-                return Err(Error::new(
-                    String::from("Internal server error"),
-                    Status::InternalServerError,
-                ))
-            }
+            .for_update()
+            .first::<i32>(conn)?;
+        let existing = games::table
+            .select(Game::as_select())
+            .filter(games::previous_game_id.eq(game_id))
+            .first::<Game>(conn)
+            .optional()?;
+        if let Some(existing) = existing {
+            return Ok((existing, false));
+        }
+
+        let new_game = NewGame {
+            status: GameStatus::to_string(GameStatus::WAITING),
+            game_slug: String::new(),
+            max_players: game.max_players,
+            rounds: game.rounds,
+            round_duration: game.round_duration,
+            visibility: game.visibility.clone(),
+            min_players: game.min_players,
+            password_hash: game.password_hash.clone(),
+            previous_game_id: Some(game_id),
+            topic_category: game.topic_category.clone(),
+            topic_difficulty: game.topic_difficulty.clone(),
+        };
+        Ok((insert_game(conn, &new_game, &acceptors)?, true))
+    });
+
+    let (rematch, created) = match result {
+        Ok(rematch) => rematch,
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    if created {
+        register_game(pools, rematch.id, &acceptors)?;
+    } else {
+        join_rematch(pools, &rematch, player_id)?;
+    }
+    Ok(Rematch {
+        accepted: acceptors.len(),
+        needed,
+        game: Some(rematch),
+        created,
+    })
+}
+
+fn join_rematch(pools: &Pools, rematch: &Game, player_id: String) -> Result<(), Error> {
+    if rematch.status != Some(GameStatus::to_string(GameStatus::WAITING)) {
+        return Err(Error::new(
+            ErrorKind::WrongGameStatus,
+            String::from("The rematch has already started"),
+        ));
+    }
+
+    let mut conn = pools.db()?;
+    let new_player = NewPlayer {
+        player_id: player_id.clone(),
+        game_id: rematch.id,
+        is_host: Some(false),
+    };
+    match diesel::insert_into(players::table)
+        .values(&new_player)
+        .on_conflict((players::player_id, players::game_id))
+        .do_update()
+        .set(players::left_game_at.eq(None::<NaiveDateTime>))
+        .execute(&mut conn)
+    {
+        Ok(_) => (),
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    let mut redis_conn = pools.redis()?;
+    match redis_conn.sadd::<String, String, bool>(format!("game:{}:players", rematch.id), player_id)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+// Slugs are short and memorable, so a collision is retried with a fresh slug. Once the
+// attempts run out the game id is appended, which can't collide.
+fn insert_game(
+    conn: &mut PgConnection,
+    new_game: &NewGame,
+    player_ids: &[String],
+) -> Result<Game, DieselError> {
+    let mut game = None;
+    for _ in 0..SLUG_ATTEMPTS {
+        game = diesel::insert_into(games::table)
+            .values(NewGame {
+                game_slug: generate_slug(),
+                ..new_game.clone()
+            })
+            .on_conflict(games::game_slug)
+            .do_nothing()
+            .returning(Game::as_returning())
+            .get_result::<Game>(conn)
+            .optional()?;
+        if game.is_some() {
+            break;
+        }
+    }
+
+    let game = match game {
+        Some(game) => game,
+        None => {
+            // The id is only known after the insert, so the row is stored under a number from
+            // the same sequence first and renamed right after.
+            let slug = generate_slug();
+            let placeholder = diesel::select(nextval("games_id_seq")).get_result::<i64>(conn)?;
+            let game = diesel::insert_into(games::table)
+                .values(NewGame {
+                    game_slug: format!("{}-{}", slug, placeholder),
+                    ..new_game.clone()
+                })
+                .returning(Game::as_returning())
+                .get_result::<Game>(conn)?;
+            diesel::update(games::table.find(game.id))
+                .set(games::game_slug.eq(format!("{}-{}", slug, game.id)))
+                .returning(Game::as_returning())
+                .get_result::<Game>(conn)?
         }
+    };
+
+    let players = player_ids
+        .iter()
+        .enumerate()
+        .map(|(index, player_id)| NewPlayer {
+            player_id: player_id.clone(),
+            game_id: game.id,
+            is_host: Some(index == 0),
+        })
+        .collect::<Vec<_>>();
+    diesel::insert_into(players::table)
+        .values(&players)
+        .execute(conn)?;
+    Ok(game)
+}
+
+fn register_game(pools: &Pools, game_id: i32, player_ids: &[String]) -> Result<(), Error> {
+    let mut redis_conn = pools.redis()?;
+    match redis::pipe()
+        .sadd("active_games", game_id)
+        .sadd(format!("game:{}:players", game_id), player_ids)
+        .query::<()>(&mut *redis_conn)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::storage(e)),
     }
+}
 
-    Ok(active_games)
+fn generate_slug() -> String {
+    let mut rng = rand::thread_rng();
+    format!(
+        "{}-{}-{}",
+        SLUG_ADJECTIVES.choose(&mut rng).unwrap(),
+        SLUG_NOUNS.choose(&mut rng).unwrap(),
+        rng.gen_range(1000..10000)
+    )
 }
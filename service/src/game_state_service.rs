@@ -1,17 +1,24 @@
-use chrono::NaiveDateTime;
+use crate::{game_service, player_service, topic_service};
+use chrono::{Duration, NaiveDateTime, Utc};
 use diesel::result::Error as DieselError;
 use diesel::{prelude::*, update};
-use infrastructure::establish_redis_connection;
-use infrastructure::models::{NewRound, Player, Round, UpdatePlayerScoringRound, UpdateRound};
+use infrastructure::models::{
+    NewPlayerScoringRound, NewRound, Player, PlayerScoringRound, Round, UpdateGame,
+    UpdatePlayerScoringRound, UpdateRound,
+};
 use infrastructure::schema::{games, player_scoring_round, players, rounds};
-use infrastructure::{establish_connection, models::Game};
+use infrastructure::{models::Game, Pools};
 use redis::Commands;
-use rocket::http::Status;
-use shared::score::calculate_score;
-use shared::{error::Error, game_status::GameStatus};
+use shared::score::{calculate_score, compare_results};
+use shared::{
+    error::{Error, ErrorKind},
+    game_status::GameStatus,
+    response::{GameState, PlayerState, RoundState},
+};
+use std::collections::HashMap;
 
-pub fn change_to_playing(game_id: i32, requester_id: String) -> Result<(), Error> {
-    let mut conn = establish_connection();
+pub fn change_to_playing(pools: &Pools, game_id: i32, requester_id: String) -> Result<(), Error> {
+    let mut conn = pools.db()?;
     let game: Result<Game, DieselError> = games::table
         .select(Game::as_select())
         .find(game_id)
@@ -28,53 +35,124 @@ pub fn change_to_playing(game_id: i32, requester_id: String) -> Result<(), Error
                     Ok(player) => player,
                     Err(diesel::NotFound) => {
                         return Err(Error::new(
+                            ErrorKind::PlayerNotFound,
                             String::from("Player not found"),
-                            Status::NotFound,
-                        ))
-                    }
-                    Err(_) => {
-                        return Err(Error::new(
-                            String::from("Internal server error"),
-                            Status::InternalServerError,
                         ))
                     }
+                    Err(e) => return Err(Error::storage(e)),
                 };
 
                 if player.is_host == Some(false) {
-                    return Err(Error::new(
+                    Err(Error::new(
+                        ErrorKind::NotHost,
                         String::from("Only the host can start the game"),
-                        Status::BadRequest,
-                    ));
+                    ))
                 } else {
-                    let mut redis_conn = establish_redis_connection();
+                    check_readiness(pools, &game, &player.player_id)?;
+
+                    let updated_game = UpdateGame {
+                        status: GameStatus::to_string(GameStatus::PLAYING),
+                        winner_id: None,
+                    };
+                    match update(games::table.find(game_id))
+                        .set(updated_game)
+                        .execute(&mut conn)
+                    {
+                        Ok(_) => (),
+                        Err(e) => return Err(Error::storage(e)),
+                    };
+
+                    let mut redis_conn = pools.redis()?;
                     match redis_conn.set::<String, String, bool>(
                         format!("game:{}:status", game_id),
                         GameStatus::to_string(GameStatus::PLAYING),
                     ) {
                         Ok(_) => Ok(()),
-                        Err(_) => Err(Error::new(
-                            String::from("Internal server error"),
-                            Status::InternalServerError,
-                        )),
+                        Err(e) => Err(Error::storage(e)),
                     }
                 }
             } else {
                 Err(Error::new(
+                    ErrorKind::WrongGameStatus,
                     String::from("Game is not in the correct status to be played"),
-                    Status::BadRequest,
                 ))
             }
         }
-        Err(diesel::NotFound) => Err(Error::new(String::from("Game not found"), Status::NotFound)),
-        Err(_) => Err(Error::new(
-            String::from("Internal server error"),
-            Status::InternalServerError,
+        Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::GameNotFound,
+            String::from("Game not found"),
         )),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+// Moves the game to playing and opens its first round. A game whose first round can't be
+// started goes back to waiting, so the host can try again.
+pub fn start_game(pools: &Pools, game_id: i32, requester_id: String) -> Result<Round, Error> {
+    change_to_playing(pools, game_id, requester_id.clone())?;
+    match start_new_round(pools, game_id, requester_id) {
+        Ok(round) => Ok(round),
+        Err(e) => {
+            reset_to_waiting(pools, game_id)?;
+            Err(e)
+        }
+    }
+}
+
+fn reset_to_waiting(pools: &Pools, game_id: i32) -> Result<(), Error> {
+    let mut conn = pools.db()?;
+    match update(games::table.find(game_id))
+        .filter(games::status.eq(GameStatus::to_string(GameStatus::PLAYING)))
+        .set(games::status.eq(GameStatus::to_string(GameStatus::WAITING)))
+        .execute(&mut conn)
+    {
+        Ok(_) => (),
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    let mut redis_conn = pools.redis()?;
+    match redis_conn.del::<String, bool>(format!("game:{}:status", game_id)) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+// The host starting the game counts as ready, everybody else has to mark themselves ready.
+fn check_readiness(pools: &Pools, game: &Game, host_id: &str) -> Result<(), Error> {
+    let mut redis_conn = pools.redis()?;
+    let (players, ready): (Vec<String>, Vec<String>) = match redis::pipe()
+        .smembers(format!("game:{}:players", game.id))
+        .smembers(format!("game:{}:ready", game.id))
+        .query(&mut *redis_conn)
+    {
+        Ok(members) => members,
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    if let Some(min_players) = game.min_players {
+        if (players.len() as i32) < min_players {
+            return Err(Error::new(
+                ErrorKind::NotEnoughPlayers,
+                format!("At least {} players are needed to start", min_players),
+            ));
+        }
+    }
+
+    let not_ready = players
+        .iter()
+        .filter(|player_id| player_id.as_str() != host_id && !ready.contains(player_id))
+        .count();
+    if not_ready > 0 {
+        return Err(Error::new(
+            ErrorKind::PlayersNotReady,
+            format!("{} players are not ready yet", not_ready),
+        ));
     }
+    Ok(())
 }
 
-pub fn change_to_finished(game_id: i32, requester_id: String) -> Result<(), Error> {
-    let mut conn = establish_connection();
+pub fn change_to_finished(pools: &Pools, game_id: i32, requester_id: String) -> Result<(), Error> {
+    let mut conn = pools.db()?;
     let game: Game = match games::table
         .select(Game::as_select())
         .find(game_id)
@@ -82,20 +160,18 @@ pub fn change_to_finished(game_id: i32, requester_id: String) -> Result<(), Erro
     {
         Ok(game) => game,
         Err(diesel::NotFound) => {
-            return Err(Error::new(String::from("Game not found"), Status::NotFound))
-        }
-        Err(_) => {
             return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
+                ErrorKind::GameNotFound,
+                String::from("Game not found"),
             ))
         }
+        Err(e) => return Err(Error::storage(e)),
     };
 
     if game.status == Some(GameStatus::to_string(GameStatus::FINISHED)) {
         return Err(Error::new(
+            ErrorKind::WrongGameStatus,
             String::from("Game is already finished"),
-            Status::BadRequest,
         ));
     }
 
@@ -108,37 +184,169 @@ pub fn change_to_finished(game_id: i32, requester_id: String) -> Result<(), Erro
         Ok(player) => player,
         Err(diesel::NotFound) => {
             return Err(Error::new(
+                ErrorKind::PlayerNotFound,
                 String::from("Player not found"),
-                Status::NotFound,
-            ))
-        }
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
             ))
         }
+        Err(e) => return Err(Error::storage(e)),
     };
 
     if player.is_host == Some(false) {
-        return Err(Error::new(
+        Err(Error::new(
+            ErrorKind::NotHost,
             String::from("Only the host can finish the game"),
-            Status::BadRequest,
-        ));
+        ))
     } else {
-        let mut redis_conn = establish_redis_connection();
+        finalize_game(&mut conn, game_id)?;
+
+        let mut redis_conn = pools.redis()?;
         match redis_conn.del::<String, bool>(format!("game:{}:status", game_id)) {
             Ok(_) => Ok(()),
-            Err(_) => Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
-            )),
+            Err(e) => Err(Error::storage(e)),
         }
     }
 }
 
-pub fn start_new_round(game_id: i32, requester_id: String) -> Result<(), Error> {
-    let mut conn = establish_connection();
+pub struct PauseState {
+    pub round_id: Option<i32>,
+    pub remaining_time: Option<i64>,
+}
+
+pub fn pause_game(pools: &Pools, game_id: i32, requester_id: String) -> Result<PauseState, Error> {
+    set_paused(pools, game_id, requester_id, true)
+}
+
+pub fn resume_game(pools: &Pools, game_id: i32, requester_id: String) -> Result<PauseState, Error> {
+    set_paused(pools, game_id, requester_id, false)
+}
+
+// Pausing keeps the time left on the open round, resuming moves its deadline so that the
+// same time is left from then on.
+fn set_paused(
+    pools: &Pools,
+    game_id: i32,
+    requester_id: String,
+    pause: bool,
+) -> Result<PauseState, Error> {
+    let (from, to) = if pause {
+        (GameStatus::PLAYING, GameStatus::PAUSED)
+    } else {
+        (GameStatus::PAUSED, GameStatus::PLAYING)
+    };
+    let (from, to) = (GameStatus::to_string(from), GameStatus::to_string(to));
+
+    let mut conn = pools.db()?;
+    let is_host = match players::table
+        .select(players::is_host)
+        .filter(players::player_id.eq(requester_id))
+        .filter(players::game_id.eq(game_id))
+        .filter(players::left_game_at.is_null())
+        .first::<Option<bool>>(&mut conn)
+    {
+        Ok(is_host) => is_host == Some(true),
+        Err(diesel::NotFound) => {
+            return Err(Error::new(
+                ErrorKind::PlayerNotFound,
+                String::from("Player not found"),
+            ))
+        }
+        Err(e) => return Err(Error::storage(e)),
+    };
+    if !is_host {
+        return Err(Error::new(
+            ErrorKind::NotHost,
+            String::from("Only the host can pause or resume the game"),
+        ));
+    }
+
+    let now = Utc::now().naive_utc();
+    let result = conn.transaction::<Option<PauseState>, DieselError, _>(|conn| {
+        let game = games::table
+            .select(Game::as_select())
+            .find(game_id)
+            .for_update()
+            .first::<Game>(conn)?;
+        if game.status.as_ref() != Some(&from) {
+            return Ok(None);
+        }
+        update(games::table.find(game_id))
+            .set(games::status.eq(to.clone()))
+            .execute(conn)?;
+
+        let round = rounds::table
+            .select(Round::as_select())
+            .filter(rounds::game_id.eq(game_id))
+            .filter(rounds::end_time.is_null())
+            .order(rounds::round_number.desc())
+            .first::<Round>(conn)
+            .optional()?;
+        let round = match round {
+            Some(round) => round,
+            None => {
+                return Ok(Some(PauseState {
+                    round_id: None,
+                    remaining_time: None,
+                }))
+            }
+        };
+
+        let remaining_time = match (pause, round.remaining_time) {
+            (true, _) => {
+                let remaining_time = (round_deadline(&round, &game) - now)
+                    .num_milliseconds()
+                    .max(0);
+                update(rounds::table.find(round.id))
+                    .set(rounds::remaining_time.eq(Some(remaining_time)))
+                    .execute(conn)?;
+                remaining_time
+            }
+            (false, Some(remaining_time)) => {
+                let paused_time =
+                    paused_time(round.start_time, game.round_duration, now, remaining_time);
+                update(rounds::table.find(round.id))
+                    .set((
+                        rounds::remaining_time.eq(None::<i64>),
+                        rounds::paused_time.eq(paused_time),
+                    ))
+                    .execute(conn)?;
+                remaining_time
+            }
+            (false, None) => (round_deadline(&round, &game) - now)
+                .num_milliseconds()
+                .max(0),
+        };
+        Ok(Some(PauseState {
+            round_id: Some(round.id),
+            remaining_time: Some(remaining_time),
+        }))
+    });
+
+    let state = match result {
+        Ok(Some(state)) => state,
+        Ok(None) if pause => {
+            return Err(Error::new(
+                ErrorKind::WrongGameStatus,
+                String::from("Only running games can be paused"),
+            ))
+        }
+        Ok(None) => {
+            return Err(Error::new(
+                ErrorKind::WrongGameStatus,
+                String::from("Game is not paused"),
+            ))
+        }
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    let mut redis_conn = pools.redis()?;
+    match redis_conn.set::<String, String, bool>(format!("game:{}:status", game_id), to) {
+        Ok(_) => Ok(state),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+pub fn start_new_round(pools: &Pools, game_id: i32, requester_id: String) -> Result<Round, Error> {
+    let mut conn = pools.db()?;
     let game: Game = match games::table
         .select(Game::as_select())
         .find(game_id)
@@ -146,20 +354,18 @@ pub fn start_new_round(game_id: i32, requester_id: String) -> Result<(), Error>
     {
         Ok(game) => game,
         Err(diesel::NotFound) => {
-            return Err(Error::new(String::from("Game not found"), Status::NotFound))
-        }
-        Err(_) => {
             return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
+                ErrorKind::GameNotFound,
+                String::from("Game not found"),
             ))
         }
+        Err(e) => return Err(Error::storage(e)),
     };
 
     if game.status != Some(GameStatus::to_string(GameStatus::PLAYING)) {
         return Err(Error::new(
+            ErrorKind::WrongGameStatus,
             String::from("Game is not in the correct status to start a new round"),
-            Status::BadRequest,
         ));
     }
 
@@ -172,85 +378,115 @@ pub fn start_new_round(game_id: i32, requester_id: String) -> Result<(), Error>
         Ok(player) => player,
         Err(diesel::NotFound) => {
             return Err(Error::new(
+                ErrorKind::PlayerNotFound,
                 String::from("Player not found"),
-                Status::NotFound,
-            ))
-        }
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
             ))
         }
+        Err(e) => return Err(Error::storage(e)),
     };
 
     if player.is_host == Some(false) {
         return Err(Error::new(
+            ErrorKind::NotHost,
             String::from("Only the host can start a new round"),
-            Status::BadRequest,
         ));
     }
 
-    let round_count: i64 = match rounds::table
-        .count()
-        .filter(rounds::game_id.eq(game_id))
-        .first::<i64>(&mut conn)
-    {
-        Ok(round_count) => round_count,
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
-            ))
-        }
-    };
+    // Picked up front, a concurrent call that wins the lock below makes this one fail anyway.
+    let topic = topic_service::select_topic(&mut conn, &game)?;
 
-    if (game.rounds as i64) == round_count {
-        return Err(Error::new(
-            String::from("All rounds have been played"),
-            Status::BadRequest,
-        ));
-    }
+    let mut redis_conn = pools.redis()?;
+    let player_ids =
+        match redis_conn.smembers::<String, Vec<String>>(format!("game:{}:players", game_id)) {
+            Ok(player_ids) => player_ids,
+            Err(e) => return Err(Error::storage(e)),
+        };
 
-    let mut redis_conn = establish_redis_connection();
+    // The game row stays locked until the round is stored, so concurrent calls agree on the
+    // round number. Nothing is written before the checks, so a rejected round commits nothing.
+    let result = conn.transaction::<Result<Round, Error>, DieselError, _>(|conn| {
+        let game = games::table
+            .select(Game::as_select())
+            .find(game_id)
+            .for_update()
+            .first::<Game>(conn)?;
+        if game.status != Some(GameStatus::to_string(GameStatus::PLAYING)) {
+            return Ok(Err(Error::new(
+                ErrorKind::WrongGameStatus,
+                String::from("Game is not in the correct status to start a new round"),
+            )));
+        }
 
-    match redis_conn.incr::<String, i32, bool>(format!("game:{}:rounds", game_id), 1) {
-        Ok(_) => (),
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
-            ))
+        let round_count: i64 = rounds::table
+            .count()
+            .filter(rounds::game_id.eq(game_id))
+            .first::<i64>(conn)?;
+        if (game.rounds as i64) == round_count {
+            return Ok(Err(Error::new(
+                ErrorKind::AllRoundsPlayed,
+                String::from("All rounds have been played"),
+            )));
         }
-    };
 
-    let new_round = NewRound {
-        game_id,
-        round_number: (round_count as i32) + 1,
-        topic: String::from(""),
-        start_time: chrono::Utc::now().naive_utc(),
+        // The timer of a round that is still open would otherwise close it during the next one.
+        let open_round = diesel::select(diesel::dsl::exists(
+            rounds::table
+                .filter(rounds::game_id.eq(game_id))
+                .filter(rounds::end_time.is_null()),
+        ))
+        .get_result::<bool>(conn)?;
+        if open_round {
+            return Ok(Err(Error::new(
+                ErrorKind::RoundNotFinished,
+                String::from("The current round has not been finished yet"),
+            )));
+        }
+
+        let new_round = NewRound {
+            game_id,
+            round_number: (round_count as i32) + 1,
+            topic,
+            start_time: chrono::Utc::now().naive_utc(),
+        };
+        let round = diesel::insert_into(rounds::table)
+            .values(&new_round)
+            .returning(Round::as_returning())
+            .get_result::<Round>(conn)?;
+
+        let scoring_rounds: Vec<NewPlayerScoringRound> = player_ids
+            .into_iter()
+            .map(|player_id| NewPlayerScoringRound {
+                player_id,
+                game_id,
+                round_id: round.id,
+            })
+            .collect();
+        diesel::insert_into(player_scoring_round::table)
+            .values(&scoring_rounds)
+            .execute(conn)?;
+
+        Ok(Ok(round))
+    });
+
+    let round = match result {
+        Ok(Ok(round)) => round,
+        Ok(Err(e)) => return Err(e),
+        Err(e) => return Err(Error::storage(e)),
     };
 
-    match diesel::insert_into(rounds::table)
-        .values(&new_round)
-        .execute(&mut conn)
+    // The counter follows the stored round instead of being incremented, so it can't drift
+    // from the database.
+    match redis_conn
+        .set::<String, i32, bool>(format!("game:{}:rounds", game_id), round.round_number)
     {
-        Ok(_) => Ok(()),
-        Err(_) => Err(Error::new(
-            String::from("Internal server error"),
-            Status::InternalServerError,
-        )),
+        Ok(_) => Ok(round),
+        Err(e) => Err(Error::storage(e)),
     }
 }
 
-pub fn finish_round(
-    game_id: i32,
-    requester_id: String,
-    first_topic: String,
-    second_topic: String,
-    third_topic: String,
-) -> Result<(), Error> {
-    let mut conn = establish_connection();
+pub fn finish_round(pools: &Pools, data: RoundResults) -> Result<Round, Error> {
+    let game_id = data.game_id;
+    let mut conn = pools.db()?;
     let game: Game = match games::table
         .select(Game::as_select())
         .find(game_id)
@@ -258,57 +494,61 @@ pub fn finish_round(
     {
         Ok(game) => game,
         Err(diesel::NotFound) => {
-            return Err(Error::new(String::from("Game not found"), Status::NotFound))
-        }
-        Err(_) => {
             return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
+                ErrorKind::GameNotFound,
+                String::from("Game not found"),
             ))
         }
+        Err(e) => return Err(Error::storage(e)),
     };
 
+    if game.status == Some(GameStatus::to_string(GameStatus::PAUSED)) {
+        return Err(Error::new(
+            ErrorKind::GamePaused,
+            String::from("Rounds can not be finished while the game is paused"),
+        ));
+    }
     if game.status != Some(GameStatus::to_string(GameStatus::PLAYING)) {
         return Err(Error::new(
+            ErrorKind::WrongGameStatus,
             String::from("Game is not in the correct status to finish a round"),
-            Status::BadRequest,
         ));
     }
 
     match players::table
         .select(Player::as_select())
-        .filter(players::player_id.eq(requester_id.clone()))
+        .filter(players::player_id.eq(data.player_id.clone()))
         .filter(players::game_id.eq(game_id))
         .first::<Player>(&mut conn)
     {
         Ok(player) => player,
         Err(diesel::NotFound) => {
             return Err(Error::new(
+                ErrorKind::PlayerNotFound,
                 String::from("Player not found"),
-                Status::NotFound,
-            ))
-        }
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
             ))
         }
+        Err(e) => return Err(Error::storage(e)),
     };
 
-    let mut redis_conn = establish_redis_connection();
+    let mut redis_conn = pools.redis()?;
 
     let current_round: i32 = match redis_conn.get::<String, i32>(format!("game:{}:rounds", game_id))
     {
         Ok(round) => round,
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
-            ))
-        }
+        Err(e) => return Err(Error::storage(e)),
     };
 
+    if data
+        .round_number
+        .is_some_and(|round_number| round_number != current_round)
+    {
+        return Err(Error::new(
+            ErrorKind::WrongRound,
+            String::from("Round is not the current round"),
+        ));
+    }
+
     let round: Round = match rounds::table
         .select(Round::as_select())
         .filter(rounds::game_id.eq(game_id))
@@ -318,103 +558,89 @@ pub fn finish_round(
         Ok(round) => round,
         Err(diesel::NotFound) => {
             return Err(Error::new(
+                ErrorKind::RoundNotFound,
                 String::from("Round not found"),
-                Status::NotFound,
-            ))
-        }
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
             ))
         }
+        Err(e) => return Err(Error::storage(e)),
     };
 
-    if let Some(_) = round.end_time {
+    if round.end_time.is_some() {
         return Err(Error::new(
+            ErrorKind::RoundAlreadyFinished,
             String::from("Round has already been finished"),
-            Status::BadRequest,
         ));
     }
 
-    let end_time = chrono::Utc::now().naive_utc();
-
-    let updated_round = UpdateRound {
-        end_time: Some(end_time),
-    };
-
-    match update(rounds::table)
-        .filter(rounds::game_id.eq(game_id))
-        .filter(rounds::round_number.eq(current_round))
-        .set(updated_round)
-        .execute(&mut conn)
-    {
-        Ok(_) => (),
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
-            ))
-        }
-    };
-
     let score = calculate_score(
         round.topic,
-        first_topic.clone(),
-        second_topic.clone(),
-        third_topic.clone(),
+        data.first_topic.clone(),
+        data.second_topic.clone(),
+        data.third_topic.clone(),
         true,
     );
 
-    let start_time_millis = round.start_time.and_utc().timestamp_millis();
-    let end_time_millis = round.start_time.and_utc().timestamp_millis();
-
-    let needed_time = end_time_millis - start_time_millis;
+    let needed_time = (data.received - round.start_time).num_milliseconds() - round.paused_time;
 
     let player_scoring = UpdatePlayerScoringRound {
-        player_id: requester_id.clone(),
+        player_id: data.player_id.clone(),
         game_id,
-        round_id: current_round,
+        round_id: round.id,
         time_used_to_complete: Some(needed_time),
         score: Some(score),
         place: None,
         is_winner: None,
-        first_topic: Some(first_topic),
-        second_topic: Some(second_topic),
-        third_topic: Some(third_topic),
+        first_topic: Some(data.first_topic),
+        second_topic: Some(data.second_topic),
+        third_topic: Some(data.third_topic),
         has_stopped_game: Some(true),
     };
 
-    match update(player_scoring_round::table)
-        .filter(player_scoring_round::game_id.eq(game_id))
-        .filter(player_scoring_round::player_id.eq(requester_id))
-        .filter(player_scoring_round::round_id.eq(current_round))
-        .set(player_scoring)
-        .execute(&mut conn)
-    {
-        Ok(_) => (),
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
-            ))
-        }
-    };
+    // Closing only an open round makes sure a round is finished once, by the first player
+    // or the timer, even when they race.
+    let result = conn.transaction::<Round, DieselError, _>(|conn| {
+        let finished_round = update(rounds::table.find(round.id))
+            .filter(rounds::end_time.is_null())
+            .set(UpdateRound {
+                end_time: Some(data.received),
+            })
+            .returning(Round::as_returning())
+            .get_result::<Round>(conn)?;
 
-    Ok(())
+        update(player_scoring_round::table)
+            .filter(player_scoring_round::game_id.eq(game_id))
+            .filter(player_scoring_round::player_id.eq(data.player_id))
+            .filter(player_scoring_round::round_id.eq(round.id))
+            .set(player_scoring)
+            .execute(conn)?;
+
+        score_outstanding(conn, &finished_round)?;
+        rank_round(conn, finished_round.id)?;
+        Ok(finished_round)
+    });
+
+    match result {
+        Ok(finished_round) => Ok(finished_round),
+        Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::RoundAlreadyFinished,
+            String::from("Round has already been finished"),
+        )),
+        Err(e) => Err(Error::storage(e)),
+    }
 }
 
 pub struct RoundResults {
     pub game_id: i32,
     pub player_id: String,
+    pub round_number: Option<i32>,
     pub first_topic: String,
     pub second_topic: String,
     pub third_topic: String,
     pub received: NaiveDateTime,
 }
 
-pub fn send_results(data: RoundResults) -> Result<(), Error> {
-    let mut conn = establish_connection();
+pub fn send_results(pools: &Pools, data: RoundResults) -> Result<Round, Error> {
+    let mut conn = pools.db()?;
     let game: Game = match games::table
         .select(Game::as_select())
         .find(data.game_id)
@@ -422,24 +648,28 @@ pub fn send_results(data: RoundResults) -> Result<(), Error> {
     {
         Ok(game) => game,
         Err(diesel::NotFound) => {
-            return Err(Error::new(String::from("Game not found"), Status::NotFound))
-        }
-        Err(_) => {
             return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
+                ErrorKind::GameNotFound,
+                String::from("Game not found"),
             ))
         }
+        Err(e) => return Err(Error::storage(e)),
     };
 
+    if game.status == Some(GameStatus::to_string(GameStatus::PAUSED)) {
+        return Err(Error::new(
+            ErrorKind::GamePaused,
+            String::from("Results can not be sent while the game is paused"),
+        ));
+    }
     if game.status != Some(GameStatus::to_string(GameStatus::PLAYING)) {
         return Err(Error::new(
+            ErrorKind::WrongGameStatus,
             String::from("Game is not in the correct status to send results"),
-            Status::BadRequest,
         ));
     }
 
-    let mut redis_conn = establish_redis_connection();
+    let mut redis_conn = pools.redis()?;
 
     match redis_conn.sismember(
         format!("game:{}:players", data.game_id),
@@ -448,29 +678,29 @@ pub fn send_results(data: RoundResults) -> Result<(), Error> {
         Ok(true) => (),
         Ok(false) => {
             return Err(Error::new(
+                ErrorKind::PlayerNotInGame,
                 String::from("Player is not in the game"),
-                Status::BadRequest,
-            ))
-        }
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
             ))
         }
+        Err(e) => return Err(Error::storage(e)),
     };
 
     let current_round: i32 =
         match redis_conn.get::<String, i32>(format!("game:{}:rounds", data.game_id)) {
             Ok(round) => round,
-            Err(_) => {
-                return Err(Error::new(
-                    String::from("Internal server error"),
-                    Status::InternalServerError,
-                ))
-            }
+            Err(e) => return Err(Error::storage(e)),
         };
 
+    if data
+        .round_number
+        .is_some_and(|round_number| round_number != current_round)
+    {
+        return Err(Error::new(
+            ErrorKind::WrongRound,
+            String::from("Round is not the current round"),
+        ));
+    }
+
     let round: Round = match rounds::table
         .select(Round::as_select())
         .filter(rounds::game_id.eq(data.game_id))
@@ -480,20 +710,22 @@ pub fn send_results(data: RoundResults) -> Result<(), Error> {
         Ok(round) => round,
         Err(diesel::NotFound) => {
             return Err(Error::new(
+                ErrorKind::RoundNotFound,
                 String::from("Round not found"),
-                Status::NotFound,
-            ))
-        }
-        Err(_) => {
-            return Err(Error::new(
-                String::from("Internal server error"),
-                Status::InternalServerError,
             ))
         }
+        Err(e) => return Err(Error::storage(e)),
     };
 
+    if round.end_time.is_some() {
+        return Err(Error::new(
+            ErrorKind::RoundAlreadyFinished,
+            String::from("Round has already been finished"),
+        ));
+    }
+
     let score = calculate_score(
-        round.topic,
+        round.topic.clone(),
         data.first_topic.clone(),
         data.second_topic.clone(),
         data.third_topic.clone(),
@@ -503,8 +735,10 @@ pub fn send_results(data: RoundResults) -> Result<(), Error> {
     let player_scoring = UpdatePlayerScoringRound {
         player_id: data.player_id.clone(),
         game_id: data.game_id,
-        round_id: current_round,
-        time_used_to_complete: None,
+        round_id: round.id,
+        time_used_to_complete: Some(
+            (data.received - round.start_time).num_milliseconds() - round.paused_time,
+        ),
         score: Some(score),
         place: None,
         is_winner: None,
@@ -517,14 +751,491 @@ pub fn send_results(data: RoundResults) -> Result<(), Error> {
     match update(player_scoring_round::table)
         .filter(player_scoring_round::game_id.eq(data.game_id))
         .filter(player_scoring_round::player_id.eq(data.player_id))
-        .filter(player_scoring_round::round_id.eq(current_round))
+        .filter(player_scoring_round::round_id.eq(round.id))
         .set(player_scoring)
         .execute(&mut conn)
     {
-        Ok(_) => Ok(()),
-        Err(_) => Err(Error::new(
-            String::from("Internal server error"),
-            Status::InternalServerError,
+        Ok(_) => Ok(round),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+// Rounds only run against a deadline while their game is playing, a paused round gets one
+// again once the game is resumed.
+pub fn get_round_deadline(pools: &Pools, round_id: i32) -> Result<Option<NaiveDateTime>, Error> {
+    let mut conn = pools.db()?;
+    let (round, game): (Round, Game) = match rounds::table
+        .inner_join(games::table)
+        .select((Round::as_select(), Game::as_select()))
+        .filter(rounds::id.eq(round_id))
+        .first::<(Round, Game)>(&mut conn)
+    {
+        Ok(result) => result,
+        Err(diesel::NotFound) => {
+            return Err(Error::new(
+                ErrorKind::RoundNotFound,
+                String::from("Round not found"),
+            ))
+        }
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    if game.status != Some(GameStatus::to_string(GameStatus::PLAYING)) {
+        return Ok(None);
+    }
+    match round.remaining_time {
+        Some(_) => Ok(None),
+        None => Ok(Some(round_deadline(&round, &game))),
+    }
+}
+
+fn round_deadline(round: &Round, game: &Game) -> NaiveDateTime {
+    deadline(round.start_time, game.round_duration, round.paused_time)
+}
+
+fn deadline(start_time: NaiveDateTime, round_duration: i32, paused_time: i64) -> NaiveDateTime {
+    start_time + Duration::seconds(round_duration as i64) + Duration::milliseconds(paused_time)
+}
+
+// The total time a round has spent paused once it resumes at `now`, chosen so that the
+// round ends `remaining_time` milliseconds from now.
+fn paused_time(
+    start_time: NaiveDateTime,
+    round_duration: i32,
+    now: NaiveDateTime,
+    remaining_time: i64,
+) -> i64 {
+    (now - start_time).num_milliseconds() + remaining_time - round_duration as i64 * 1000
+}
+
+pub fn get_open_rounds(pools: &Pools) -> Result<Vec<Round>, Error> {
+    let mut conn = pools.db()?;
+    match rounds::table
+        .inner_join(games::table)
+        .select(Round::as_select())
+        .filter(games::status.eq(GameStatus::to_string(GameStatus::PLAYING)))
+        .filter(rounds::end_time.is_null())
+        .get_results::<Round>(&mut conn)
+    {
+        Ok(rounds) => Ok(rounds),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+pub fn get_current_round(pools: &Pools, game_id: i32) -> Result<Round, Error> {
+    let mut conn = pools.db()?;
+    match rounds::table
+        .select(Round::as_select())
+        .filter(rounds::game_id.eq(game_id))
+        .order(rounds::round_number.desc())
+        .first::<Round>(&mut conn)
+    {
+        Ok(round) => Ok(round),
+        Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::RoundNotFound,
+            String::from("Round not found"),
         )),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+pub fn get_game_state(pools: &Pools, game_id: i32) -> Result<GameState, Error> {
+    let game = game_service::get_game(pools, Some(game_id), None)?;
+
+    let mut redis_conn = pools.redis()?;
+    let ready = match redis_conn.smembers::<String, Vec<String>>(format!("game:{}:ready", game_id))
+    {
+        Ok(ready) => ready,
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    let players = match game.status.clone().map(GameStatus::from_string) {
+        Some(GameStatus::FINISHED) | Some(GameStatus::ABANDONED) => Vec::new(),
+        _ => player_service::get_players_in_game(pools, game_id)?
+            .into_iter()
+            .map(|player| PlayerState {
+                is_ready: ready.contains(&player.player_id),
+                player_id: player.player_id,
+                is_host: player.is_host.unwrap_or(false),
+            })
+            .collect(),
+    };
+
+    let round_number =
+        match redis_conn.get::<String, Option<i32>>(format!("game:{}:rounds", game_id)) {
+            Ok(round_number) => round_number,
+            Err(e) => return Err(Error::storage(e)),
+        };
+
+    // The counter is gone once a game is cleaned up, the latest round is the current one then.
+    let mut conn = pools.db()?;
+    let round = match round_number {
+        Some(round_number) => rounds::table
+            .select(Round::as_select())
+            .filter(rounds::game_id.eq(game_id))
+            .filter(rounds::round_number.eq(round_number))
+            .first::<Round>(&mut conn)
+            .optional(),
+        None => rounds::table
+            .select(Round::as_select())
+            .filter(rounds::game_id.eq(game_id))
+            .order(rounds::round_number.desc())
+            .first::<Round>(&mut conn)
+            .optional(),
+    };
+    let round = match round {
+        Ok(round) => round,
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    let current_round = match round {
+        Some(round) => {
+            let submitted = match player_scoring_round::table
+                .select(player_scoring_round::player_id)
+                .filter(player_scoring_round::round_id.eq(round.id))
+                .filter(player_scoring_round::first_topic.is_not_null())
+                .order(player_scoring_round::updated_at)
+                .get_results::<String>(&mut conn)
+            {
+                Ok(submitted) => submitted,
+                Err(e) => return Err(Error::storage(e)),
+            };
+
+            let remaining_time = match (round.end_time, round.remaining_time) {
+                (Some(_), _) => 0,
+                (None, Some(remaining_time)) => remaining_time,
+                (None, None) => (round_deadline(&round, &game) - Utc::now().naive_utc())
+                    .num_milliseconds()
+                    .max(0),
+            };
+
+            Some(RoundState {
+                round_id: round.id,
+                round_number: round.round_number,
+                topic: round.topic,
+                start_time: round.start_time.and_utc().timestamp_millis(),
+                end_time: round
+                    .end_time
+                    .map(|end_time| end_time.and_utc().timestamp_millis()),
+                remaining_time,
+                submitted,
+            })
+        }
+        None => None,
+    };
+
+    Ok(GameState {
+        game_id: game.id,
+        game_slug: game.game_slug,
+        game_status: game
+            .status
+            .unwrap_or_else(|| GameStatus::to_string(GameStatus::WAITING)),
+        max_players: game.max_players,
+        min_players: game.min_players,
+        rounds: game.rounds,
+        round_duration: game.round_duration,
+        winner_id: game.winner_id,
+        players,
+        current_round,
+    })
+}
+
+pub fn expire_round(pools: &Pools, round_id: i32) -> Result<Option<Round>, Error> {
+    let deadline = match get_round_deadline(pools, round_id)? {
+        Some(deadline) => deadline,
+        None => return Ok(None),
+    };
+    let mut conn = pools.db()?;
+
+    let updated_round = UpdateRound {
+        end_time: Some(deadline),
+    };
+
+    // Only open rounds of running games are closed, so a round finished by a player or a game
+    // paused or finished in the meantime is left untouched.
+    let playing_games = games::table
+        .select(games::id)
+        .filter(games::status.eq(GameStatus::to_string(GameStatus::PLAYING)));
+    let result = conn.transaction::<Round, DieselError, _>(|conn| {
+        let round = update(rounds::table.find(round_id))
+            .filter(rounds::game_id.eq_any(playing_games))
+            .filter(rounds::end_time.is_null())
+            .filter(rounds::remaining_time.is_null())
+            .set(updated_round)
+            .returning(Round::as_returning())
+            .get_result::<Round>(conn)?;
+
+        score_outstanding(conn, &round)?;
+        rank_round(conn, round.id)?;
+        Ok(round)
+    });
+
+    match result {
+        Ok(round) => Ok(Some(round)),
+        Err(diesel::NotFound) => Ok(None),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+fn score_outstanding(conn: &mut PgConnection, round: &Round) -> QueryResult<()> {
+    let outstanding: Vec<PlayerScoringRound> = player_scoring_round::table
+        .select(PlayerScoringRound::as_select())
+        .filter(player_scoring_round::round_id.eq(round.id))
+        .filter(player_scoring_round::score.is_null())
+        .get_results::<PlayerScoringRound>(conn)?;
+
+    for stat in outstanding {
+        let score = calculate_score(
+            round.topic.clone(),
+            stat.first_topic.unwrap_or_default(),
+            stat.second_topic.unwrap_or_default(),
+            stat.third_topic.unwrap_or_default(),
+            false,
+        );
+
+        update(player_scoring_round::table)
+            .filter(player_scoring_round::round_id.eq(round.id))
+            .filter(player_scoring_round::player_id.eq(stat.player_id))
+            .set(player_scoring_round::score.eq(score))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+// Places players by score, the faster player winning a tie. Players without a recorded
+// completion time are placed after everyone with the same score.
+pub fn rank_round(conn: &mut PgConnection, round_id: i32) -> QueryResult<()> {
+    let mut stats: Vec<PlayerScoringRound> = player_scoring_round::table
+        .select(PlayerScoringRound::as_select())
+        .filter(player_scoring_round::round_id.eq(round_id))
+        .get_results::<PlayerScoringRound>(conn)?;
+
+    sort_round_results(&mut stats);
+
+    for (index, stat) in stats.iter().enumerate() {
+        let place = index as i32 + 1;
+        let is_winner = place == 1 && stat.score.unwrap_or(0) > 0;
+
+        update(player_scoring_round::table)
+            .filter(player_scoring_round::round_id.eq(round_id))
+            .filter(player_scoring_round::player_id.eq(stat.player_id.clone()))
+            .set((
+                player_scoring_round::place.eq(place),
+                player_scoring_round::is_winner.eq(is_winner),
+            ))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+pub struct GameStanding {
+    pub player_id: String,
+    pub score: i32,
+    pub time_used: i64,
+    pub best_round_score: i32,
+}
+
+fn sort_round_results(stats: &mut [PlayerScoringRound]) {
+    stats.sort_by(|a, b| {
+        compare_results(
+            a.score.unwrap_or(0),
+            a.time_used_to_complete.unwrap_or(i64::MAX),
+            b.score.unwrap_or(0),
+            b.time_used_to_complete.unwrap_or(i64::MAX),
+        )
+    });
+}
+
+fn final_standings(conn: &mut PgConnection, game_id: i32) -> QueryResult<Vec<GameStanding>> {
+    let stats: Vec<PlayerScoringRound> = player_scoring_round::table
+        .select(PlayerScoringRound::as_select())
+        .filter(player_scoring_round::game_id.eq(game_id))
+        .get_results::<PlayerScoringRound>(conn)?;
+    Ok(rank_standings(stats))
+}
+
+// Orders the players of a game by their total score over all rounds, with the lower total
+// completion time breaking a tie.
+fn rank_standings(stats: Vec<PlayerScoringRound>) -> Vec<GameStanding> {
+    let mut standings: HashMap<String, GameStanding> = HashMap::new();
+    for stat in stats {
+        let score = stat.score.unwrap_or(0);
+        let standing = standings
+            .entry(stat.player_id.clone())
+            .or_insert(GameStanding {
+                player_id: stat.player_id,
+                score: 0,
+                time_used: 0,
+                best_round_score: 0,
+            });
+        standing.score += score;
+        standing.time_used += stat.time_used_to_complete.unwrap_or(0);
+        standing.best_round_score = standing.best_round_score.max(score);
+    }
+
+    let mut standings: Vec<GameStanding> = standings.into_values().collect();
+    standings.sort_by(|a, b| compare_results(a.score, a.time_used, b.score, b.time_used));
+    standings
+}
+
+// Marks the game as finished, sets its winner and adds the results to the lifetime scoring
+// of every participant, all within one transaction. A round that is still open is closed and
+// scored first. The game row stays locked while the results are aggregated, so a game that
+// is finished twice at once is only counted once.
+pub fn finalize_game(conn: &mut PgConnection, game_id: i32) -> Result<(), Error> {
+    let result = conn.transaction::<bool, DieselError, _>(|conn| {
+        let game = games::table
+            .select(Game::as_select())
+            .find(game_id)
+            .for_update()
+            .first::<Game>(conn)?;
+        let running = [
+            GameStatus::to_string(GameStatus::PLAYING),
+            GameStatus::to_string(GameStatus::PAUSED),
+        ];
+        if !game.status.is_some_and(|status| running.contains(&status)) {
+            return Ok(false);
+        }
+
+        let open_rounds = update(rounds::table)
+            .filter(rounds::game_id.eq(game_id))
+            .filter(rounds::end_time.is_null())
+            .set(rounds::end_time.eq(Some(Utc::now().naive_utc())))
+            .returning(Round::as_returning())
+            .get_results::<Round>(conn)?;
+        for round in open_rounds {
+            score_outstanding(conn, &round)?;
+            rank_round(conn, round.id)?;
+        }
+
+        let standings = final_standings(conn, game_id)?;
+        let winner_id = standings
+            .first()
+            .filter(|standing| standing.score > 0)
+            .map(|standing| standing.player_id.clone());
+
+        let updated_game = UpdateGame {
+            status: GameStatus::to_string(GameStatus::FINISHED),
+            winner_id: winner_id.clone(),
+        };
+        update(games::table.find(game_id))
+            .set(updated_game)
+            .execute(conn)?;
+
+        player_service::record_game_results(conn, &standings, winner_id.as_deref())?;
+        Ok(true)
+    });
+
+    match result {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Error::new(
+            ErrorKind::WrongGameStatus,
+            String::from("Only running games can be finished"),
+        )),
+        Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::GameNotFound,
+            String::from("Game not found"),
+        )),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 20)
+            .unwrap()
+            .and_hms_opt(hour, minute, second)
+            .unwrap()
+    }
+
+    fn stat(
+        player_id: &str,
+        round_id: i32,
+        score: i32,
+        time_used: Option<i64>,
+    ) -> PlayerScoringRound {
+        PlayerScoringRound {
+            player_id: player_id.to_string(),
+            game_id: 1,
+            round_id,
+            score: Some(score),
+            place: None,
+            is_winner: None,
+            time_used_to_complete: time_used,
+            first_topic: None,
+            second_topic: None,
+            third_topic: None,
+            has_stopped_game: None,
+            created_at: at(12, 0, 0),
+            updated_at: at(12, 0, 0),
+        }
+    }
+
+    #[test]
+    fn standings_add_up_all_rounds() {
+        let standings = rank_standings(vec![
+            stat("ana", 1, 500, Some(20_000)),
+            stat("ben", 1, 400, Some(30_000)),
+            stat("ana", 2, 0, Some(60_000)),
+            stat("ben", 2, 300, Some(10_000)),
+        ]);
+
+        let order: Vec<_> = standings.iter().map(|s| s.player_id.as_str()).collect();
+        assert_eq!(order, ["ben", "ana"]);
+        assert_eq!(standings[0].score, 700);
+        assert_eq!(standings[0].time_used, 40_000);
+        assert_eq!(standings[0].best_round_score, 400);
+        assert_eq!(standings[1].best_round_score, 500);
+    }
+
+    #[test]
+    fn standings_tie_goes_to_the_faster_player() {
+        let standings = rank_standings(vec![
+            stat("ana", 1, 500, Some(50_000)),
+            stat("ben", 1, 500, Some(20_000)),
+            stat("cleo", 1, 400, Some(5_000)),
+        ]);
+
+        let order: Vec<_> = standings.iter().map(|s| s.player_id.as_str()).collect();
+        assert_eq!(order, ["ben", "ana", "cleo"]);
+    }
+
+    #[test]
+    fn round_tie_puts_players_without_a_time_last() {
+        let mut stats = vec![
+            stat("ana", 1, 300, None),
+            stat("ben", 1, 300, Some(45_000)),
+            stat("cleo", 1, 1000, Some(50_000)),
+            stat("dan", 1, 300, Some(15_000)),
+        ];
+        sort_round_results(&mut stats);
+
+        let order: Vec<_> = stats.iter().map(|s| s.player_id.as_str()).collect();
+        assert_eq!(order, ["cleo", "dan", "ben", "ana"]);
+    }
+
+    #[test]
+    fn resumed_round_ends_after_its_remaining_time() {
+        let start = at(12, 0, 0);
+
+        // Paused 20 seconds into a 60 second round and resumed at 12:01:00.
+        let remaining = (deadline(start, 60, 0) - at(12, 0, 20)).num_milliseconds();
+        assert_eq!(remaining, 40_000);
+        let paused = paused_time(start, 60, at(12, 1, 0), remaining);
+        assert_eq!(paused, 40_000);
+        assert_eq!(deadline(start, 60, paused), at(12, 1, 40));
+
+        // A second pause at 12:01:10, resumed at 12:02:00, adds its time on top.
+        let remaining = (deadline(start, 60, paused) - at(12, 1, 10)).num_milliseconds();
+        assert_eq!(remaining, 30_000);
+        let paused = paused_time(start, 60, at(12, 2, 0), remaining);
+        assert_eq!(paused, 90_000);
+        assert_eq!(deadline(start, 60, paused), at(12, 2, 30));
     }
 }
@@ -0,0 +1,285 @@
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use infrastructure::{
+    models::{Game, GameInvite, NewGameInvite},
+    schema::{friends, game_invites, games, players},
+    DbConnection, Pools,
+};
+use rand::seq::SliceRandom;
+use shared::{
+    error::{Error, ErrorKind},
+    game_status::GameStatus,
+    game_visibility::GameVisibility,
+    request::JoinGame,
+};
+
+// Leaves out characters that are easily mixed up when an invite is read out loud.
+const INVITE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const INVITE_LENGTH: usize = 8;
+const INVITE_ATTEMPTS: usize = 5;
+
+pub fn create_invite(
+    pools: &Pools,
+    game_id: i32,
+    requester_id: String,
+) -> Result<GameInvite, Error> {
+    let mut conn = pools.db()?;
+    let game = find_game(&mut conn, game_id)?;
+    if game.status != Some(GameStatus::to_string(GameStatus::WAITING)) {
+        return Err(Error::new(
+            ErrorKind::WrongGameStatus,
+            String::from("Invites can only be created for a lobby"),
+        ));
+    }
+    check_host(&mut conn, game_id, &requester_id)?;
+
+    for _ in 0..INVITE_ATTEMPTS {
+        let invite = diesel::insert_into(game_invites::table)
+            .values(&NewGameInvite {
+                game_id,
+                code: generate_code(),
+                created_by: requester_id.clone(),
+            })
+            .on_conflict(game_invites::code)
+            .do_nothing()
+            .returning(GameInvite::as_returning())
+            .get_result::<GameInvite>(&mut conn)
+            .optional();
+        match invite {
+            Ok(Some(invite)) => return Ok(invite),
+            Ok(None) => continue,
+            Err(e) => return Err(Error::storage(e)),
+        }
+    }
+
+    Err(Error::new(
+        ErrorKind::Internal,
+        String::from("Could not generate a unique invite code"),
+    ))
+}
+
+pub fn revoke_invite(
+    pools: &Pools,
+    game_id: i32,
+    requester_id: String,
+    code: String,
+) -> Result<(), Error> {
+    let mut conn = pools.db()?;
+    find_game(&mut conn, game_id)?;
+    check_host(&mut conn, game_id, &requester_id)?;
+
+    match diesel::update(game_invites::table)
+        .filter(game_invites::game_id.eq(game_id))
+        .filter(game_invites::code.eq(code.to_uppercase()))
+        .filter(game_invites::revoked_at.is_null())
+        .set(game_invites::revoked_at.eq(Utc::now().naive_utc()))
+        .execute(&mut conn)
+    {
+        Ok(0) => Err(Error::new(
+            ErrorKind::InviteNotFound,
+            String::from("Invite not found"),
+        )),
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+pub fn resolve_invite(pools: &Pools, code: String) -> Result<Game, Error> {
+    let mut conn = pools.db()?;
+    match game_invites::table
+        .inner_join(games::table)
+        .select(Game::as_select())
+        .filter(game_invites::code.eq(code.to_uppercase()))
+        .filter(game_invites::revoked_at.is_null())
+        .first::<Game>(&mut conn)
+    {
+        Ok(game) => Ok(game),
+        Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::InviteNotFound,
+            String::from("Invite not found"),
+        )),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    if !(4..=64).contains(&password.chars().count()) {
+        return Err(Error::new(
+            ErrorKind::InvalidRequest,
+            String::from("A game password needs between 4 and 64 characters"),
+        ));
+    }
+    let salt = match SaltString::encode_b64(&rand::random::<[u8; 16]>()) {
+        Ok(salt) => salt,
+        Err(e) => {
+            return Err(Error::with_cause(
+                ErrorKind::Internal,
+                String::from("Could not hash the game password"),
+                e,
+            ))
+        }
+    };
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(e) => Err(Error::with_cause(
+            ErrorKind::Internal,
+            String::from("Could not hash the game password"),
+            e,
+        )),
+    }
+}
+
+// A valid invite lets a player in regardless of the visibility and password of the game.
+pub(crate) fn check_access(
+    conn: &mut DbConnection,
+    game: &Game,
+    player_id: &str,
+    access: &JoinGame,
+) -> Result<(), Error> {
+    if let Some(code) = &access.invite_code {
+        let invited = match game_invites::table
+            .count()
+            .filter(game_invites::game_id.eq(game.id))
+            .filter(game_invites::code.eq(code.to_uppercase()))
+            .filter(game_invites::revoked_at.is_null())
+            .get_result::<i64>(conn)
+        {
+            Ok(invited) => invited > 0,
+            Err(e) => return Err(Error::storage(e)),
+        };
+        if invited {
+            return Ok(());
+        }
+        return Err(Error::new(
+            ErrorKind::InviteNotFound,
+            String::from("Invite not found"),
+        ));
+    }
+
+    match GameVisibility::from_string(game.visibility.clone())? {
+        GameVisibility::PUBLIC => (),
+        GameVisibility::PRIVATE => {
+            return Err(Error::new(
+                ErrorKind::JoinNotAllowed,
+                String::from("Private games can only be joined with an invite"),
+            ))
+        }
+        GameVisibility::FRIENDS => {
+            if !is_friend_of_host(conn, game.id, player_id)? {
+                return Err(Error::new(
+                    ErrorKind::JoinNotAllowed,
+                    String::from("Only friends of the host can join this game"),
+                ));
+            }
+        }
+    }
+
+    let password_hash = match &game.password_hash {
+        Some(password_hash) => password_hash,
+        None => return Ok(()),
+    };
+    let parsed_hash = match PasswordHash::new(password_hash) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(e) => {
+            return Err(Error::with_cause(
+                ErrorKind::Internal,
+                String::from("Invalid game password hash"),
+                e,
+            ))
+        }
+    };
+    let password = access.password.clone().unwrap_or_default();
+    match Argon2::default().verify_password(password.as_bytes(), &parsed_hash) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::new(
+            ErrorKind::WrongPassword,
+            String::from("Wrong game password"),
+        )),
+    }
+}
+
+fn is_friend_of_host(
+    conn: &mut DbConnection,
+    game_id: i32,
+    player_id: &str,
+) -> Result<bool, Error> {
+    let host_id = match players::table
+        .select(players::player_id)
+        .filter(players::game_id.eq(game_id))
+        .filter(players::is_host.eq(true))
+        .filter(players::left_game_at.is_null())
+        .first::<String>(conn)
+        .optional()
+    {
+        Ok(Some(host_id)) => host_id,
+        Ok(None) => return Ok(false),
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    // Friendships are stored in one direction only, so both are checked.
+    match friends::table
+        .count()
+        .filter(
+            friends::user_id
+                .eq(host_id.clone())
+                .and(friends::friend_id.eq(player_id.to_string())),
+        )
+        .or_filter(
+            friends::user_id
+                .eq(player_id.to_string())
+                .and(friends::friend_id.eq(host_id)),
+        )
+        .get_result::<i64>(conn)
+    {
+        Ok(count) => Ok(count > 0),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+fn find_game(conn: &mut DbConnection, game_id: i32) -> Result<Game, Error> {
+    let game: Result<Game, DieselError> = games::table
+        .select(Game::as_select())
+        .find(game_id)
+        .first::<Game>(conn);
+    match game {
+        Ok(game) => Ok(game),
+        Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::GameNotFound,
+            String::from("Game not found"),
+        )),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+fn check_host(conn: &mut DbConnection, game_id: i32, requester_id: &str) -> Result<(), Error> {
+    let is_host = match players::table
+        .select(players::is_host)
+        .filter(players::player_id.eq(requester_id.to_string()))
+        .filter(players::game_id.eq(game_id))
+        .filter(players::left_game_at.is_null())
+        .first::<Option<bool>>(conn)
+    {
+        Ok(is_host) => is_host == Some(true),
+        Err(diesel::NotFound) => false,
+        Err(e) => return Err(Error::storage(e)),
+    };
+    if !is_host {
+        return Err(Error::new(
+            ErrorKind::NotHost,
+            String::from("Only the host can manage invites"),
+        ));
+    }
+    Ok(())
+}
+
+fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..INVITE_LENGTH)
+        .map(|_| *INVITE_ALPHABET.choose(&mut rng).unwrap() as char)
+        .collect()
+}
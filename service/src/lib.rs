@@ -1,3 +1,6 @@
 pub mod game_service;
 pub mod game_state_service;
+pub mod invite_service;
+pub mod matchmaking_service;
 pub mod player_service;
+pub mod topic_service;
@@ -0,0 +1,306 @@
+use crate::game_service;
+use crate::topic_service::check_topics;
+use chrono::Utc;
+use infrastructure::{models::Game, Pools, RedisConnection};
+use redis::Commands;
+use shared::{
+    error::{Error, ErrorKind},
+    request::{CreateGame, QueuePreferences},
+    response::MatchFound,
+};
+use std::{collections::HashMap, time::Duration};
+
+const QUEUE_KEY: &str = "matchmaking:queue";
+const PREFERENCES_KEY: &str = "matchmaking:preferences";
+// How long a found match can still be picked up by polling.
+const MATCH_TTL_SECONDS: u64 = 600;
+
+pub struct Match {
+    pub game: Game,
+    pub player_ids: Vec<String>,
+}
+
+// The groups that could not be turned into a game are reported next to the matches. Their
+// players are back in the queue after a storage failure and dropped from it otherwise.
+pub struct MatchRun {
+    pub matches: Vec<Match>,
+    pub failures: Vec<Error>,
+}
+
+fn match_key(player_id: &str) -> String {
+    format!("matchmaking:match:{}", player_id)
+}
+
+pub fn join_queue(
+    pools: &Pools,
+    player_id: String,
+    preferences: QueuePreferences,
+) -> Result<QueuePreferences, Error> {
+    if !(2..=16).contains(&preferences.max_players) {
+        return Err(Error::new(
+            ErrorKind::InvalidRequest,
+            String::from("A game needs between 2 and 16 players"),
+        ));
+    }
+    if !(1..=20).contains(&preferences.rounds) {
+        return Err(Error::new(
+            ErrorKind::InvalidRequest,
+            String::from("A game needs between 1 and 20 rounds"),
+        ));
+    }
+    // Checked up front, a group that can never get a game would otherwise stay queued.
+    let mut conn = pools.db()?;
+    check_topics(
+        &mut conn,
+        preferences.topic_category.as_deref(),
+        None,
+        preferences.rounds,
+    )?;
+    let payload = match serde_json::to_string(&preferences) {
+        Ok(payload) => payload,
+        Err(e) => {
+            return Err(Error::with_cause(
+                ErrorKind::Internal,
+                String::from("Could not queue the player"),
+                e,
+            ))
+        }
+    };
+
+    let mut redis_conn = pools.redis()?;
+    // Queueing again with new preferences moves the player to the back of the queue.
+    match redis::pipe()
+        .del(match_key(&player_id))
+        .hset(PREFERENCES_KEY, player_id.clone(), payload)
+        .zadd(QUEUE_KEY, player_id, Utc::now().timestamp_millis())
+        .query::<()>(&mut *redis_conn)
+    {
+        Ok(_) => Ok(preferences),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+pub fn leave_queue(pools: &Pools, player_id: String) -> Result<(), Error> {
+    let mut redis_conn = pools.redis()?;
+    match redis::pipe()
+        .zrem(QUEUE_KEY, player_id.clone())
+        .hdel(PREFERENCES_KEY, player_id)
+        .query::<()>(&mut *redis_conn)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+// The last match found for a player, for clients that are not connected to the matchmaking
+// stream when it is made.
+pub fn get_match(pools: &Pools, player_id: String) -> Result<MatchFound, Error> {
+    let mut redis_conn = pools.redis()?;
+    let payload = match redis_conn.get::<String, Option<String>>(match_key(&player_id)) {
+        Ok(Some(payload)) => payload,
+        Ok(None) => {
+            return Err(Error::new(
+                ErrorKind::MatchNotFound,
+                String::from("No match found yet"),
+            ))
+        }
+        Err(e) => return Err(Error::storage(e)),
+    };
+    match serde_json::from_str::<MatchFound>(&payload) {
+        Ok(found) => Ok(found),
+        Err(e) => Err(Error::with_cause(
+            ErrorKind::Internal,
+            String::from("Could not read the match"),
+            e,
+        )),
+    }
+}
+
+// Groups queued players with the same preferences into new games. A group is matched once
+// it can fill a game, or once its longest waiting player has waited for `max_wait` and
+// there are enough players to start. A failing group does not keep the others from being
+// matched.
+pub fn find_matches(pools: &Pools, max_wait: Duration) -> Result<MatchRun, Error> {
+    let mut redis_conn = pools.redis()?;
+    let queue = match redis_conn.zrange_withscores::<&str, Vec<(String, i64)>>(QUEUE_KEY, 0, -1) {
+        Ok(queue) => queue,
+        Err(e) => return Err(Error::storage(e)),
+    };
+    let stored = match redis_conn.hgetall::<&str, HashMap<String, String>>(PREFERENCES_KEY) {
+        Ok(stored) => stored,
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    let mut groups: Vec<(QueuePreferences, Vec<(String, i64)>)> = Vec::new();
+    let mut broken = Vec::new();
+    for (player_id, queued_at) in queue {
+        let preferences = match stored
+            .get(&player_id)
+            .and_then(|payload| serde_json::from_str::<QueuePreferences>(payload).ok())
+        {
+            Some(preferences) => preferences,
+            None => {
+                broken.push(player_id);
+                continue;
+            }
+        };
+        match groups.iter_mut().find(|(group, _)| *group == preferences) {
+            Some((_, players)) => players.push((player_id, queued_at)),
+            None => groups.push((preferences, vec![(player_id, queued_at)])),
+        }
+    }
+    if !broken.is_empty() {
+        if let Err(e) = redis_conn.zrem::<&str, Vec<String>, i32>(QUEUE_KEY, broken) {
+            return Err(Error::storage(e));
+        }
+    }
+
+    let now = Utc::now().timestamp_millis();
+    let mut run = MatchRun {
+        matches: Vec::new(),
+        failures: Vec::new(),
+    };
+    for (preferences, players) in groups {
+        for chunk in players.chunks(preferences.max_players as usize) {
+            let waited = now - chunk[0].1;
+            if chunk.len() < preferences.max_players as usize
+                && (chunk.len() < 2 || waited < max_wait.as_millis() as i64)
+            {
+                continue;
+            }
+
+            match match_group(pools, &mut redis_conn, chunk, &preferences) {
+                Ok(Some(found)) => run.matches.push(found),
+                Ok(None) => (),
+                Err(e) => run.failures.push(e),
+            }
+        }
+    }
+
+    Ok(run)
+}
+
+fn match_group(
+    pools: &Pools,
+    redis_conn: &mut RedisConnection,
+    players: &[(String, i64)],
+    preferences: &QueuePreferences,
+) -> Result<Option<Match>, Error> {
+    let claimed = claim_players(redis_conn, players)?;
+    if claimed.len() < 2 {
+        requeue_players(redis_conn, &claimed, preferences)?;
+        return Ok(None);
+    }
+
+    let player_ids = claimed
+        .iter()
+        .map(|(player_id, _)| player_id.clone())
+        .collect::<Vec<_>>();
+    let options = CreateGame {
+        max_players: preferences.max_players,
+        rounds: preferences.rounds,
+        round_duration: None,
+        visibility: None,
+        min_players: None,
+        password: None,
+        topic_category: preferences.topic_category.clone(),
+        topic_difficulty: None,
+    };
+    let game = match game_service::create_game_with_players(pools, player_ids.clone(), options) {
+        Ok(game) => game,
+        // Only storage failures are worth another try, the players of a group that was
+        // rejected are dropped from the queue.
+        Err(e) if matches!(e.kind, ErrorKind::Storage | ErrorKind::Unavailable) => {
+            requeue_players(redis_conn, &claimed, preferences)?;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+
+    store_match(redis_conn, &game, &player_ids)?;
+    Ok(Some(Match { game, player_ids }))
+}
+
+fn store_match(
+    redis_conn: &mut RedisConnection,
+    game: &Game,
+    player_ids: &[String],
+) -> Result<(), Error> {
+    let found = MatchFound {
+        game_id: game.id,
+        game_slug: game.game_slug.clone(),
+    };
+    let payload = match serde_json::to_string(&found) {
+        Ok(payload) => payload,
+        Err(e) => {
+            return Err(Error::with_cause(
+                ErrorKind::Internal,
+                String::from("Could not store the match"),
+                e,
+            ))
+        }
+    };
+
+    let mut pipe = redis::pipe();
+    for player_id in player_ids {
+        pipe.set_ex(match_key(player_id), payload.clone(), MATCH_TTL_SECONDS);
+    }
+    match pipe.query::<()>(&mut **redis_conn) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+// Players who left the queue in the meantime are skipped.
+fn claim_players(
+    redis_conn: &mut RedisConnection,
+    players: &[(String, i64)],
+) -> Result<Vec<(String, i64)>, Error> {
+    let mut claimed = Vec::new();
+    for (player_id, queued_at) in players {
+        match redis_conn.zrem::<&str, &str, i32>(QUEUE_KEY, player_id) {
+            Ok(1) => claimed.push((player_id.clone(), *queued_at)),
+            Ok(_) => (),
+            Err(e) => return Err(Error::storage(e)),
+        }
+    }
+    if claimed.is_empty() {
+        return Ok(claimed);
+    }
+
+    let player_ids = claimed
+        .iter()
+        .map(|(player_id, _)| player_id.clone())
+        .collect::<Vec<_>>();
+    match redis_conn.hdel::<&str, Vec<String>, i32>(PREFERENCES_KEY, player_ids) {
+        Ok(_) => Ok(claimed),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+fn requeue_players(
+    redis_conn: &mut RedisConnection,
+    players: &[(String, i64)],
+    preferences: &QueuePreferences,
+) -> Result<(), Error> {
+    let payload = match serde_json::to_string(preferences) {
+        Ok(payload) => payload,
+        Err(e) => {
+            return Err(Error::with_cause(
+                ErrorKind::Internal,
+                String::from("Could not queue the player"),
+                e,
+            ))
+        }
+    };
+
+    let mut pipe = redis::pipe();
+    for (player_id, queued_at) in players {
+        pipe.hset(PREFERENCES_KEY, player_id, payload.clone())
+            .zadd(QUEUE_KEY, player_id, *queued_at);
+    }
+    match pipe.query::<()>(&mut **redis_conn) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
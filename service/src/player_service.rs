@@ -1,20 +1,30 @@
+use crate::game_state_service::GameStanding;
+use crate::invite_service;
+use chrono::NaiveDateTime;
 use diesel::result::Error as DieselError;
-use diesel::{prelude::*, update};
-use infrastructure::models::UpdatePlayer;
-use infrastructure::schema::player_scoring_round;
+use diesel::sql_types::Integer;
+use diesel::upsert::excluded;
+use diesel::{define_sql_function, insert_into, prelude::*, update};
+use infrastructure::models::{
+    NewGameBan, NewPlayer, NewPlayerScoring, PlayerScoring, UpdatePlayer,
+};
+use infrastructure::schema::{game_bans, player_scoring, player_scoring_round};
 use infrastructure::{
-    establish_connection, establish_redis_connection,
     models::{Game, Player, PlayerScoringRound},
-    schema::{games, players},
+    schema::{games, players, rounds},
+    Pools,
 };
 use redis::Commands;
-use rocket::http::Status;
-use shared::error::Error;
+use shared::error::{Error, ErrorKind};
 use shared::game_status::GameStatus;
+use shared::request::JoinGame;
 use shared::response::{GamePlayerStats, PlayerRoundStats};
+use shared::score::{calculate_xp, compare_results};
+
+define_sql_function!(fn greatest(a: Integer, b: Integer) -> Integer);
 
-pub fn get_players_in_game(game_id: i32) -> Result<Vec<Player>, Error> {
-    let mut conn = establish_connection();
+pub fn get_players_in_game(pools: &Pools, game_id: i32) -> Result<Vec<Player>, Error> {
+    let mut conn = pools.db()?;
     let game: Result<Game, DieselError> = games::table
         .select(Game::as_select())
         .find(game_id)
@@ -23,18 +33,14 @@ pub fn get_players_in_game(game_id: i32) -> Result<Vec<Player>, Error> {
         Ok(game) => {
             if game.status == Some(String::from("waiting"))
                 || game.status == Some(String::from("playing"))
+                || game.status == Some(String::from("paused"))
             {
-                let mut redis_conn = establish_redis_connection();
+                let mut redis_conn = pools.redis()?;
                 let player_ids = match redis_conn
                     .smembers::<String, Vec<String>>(format!("game:{}:players", game_id))
                 {
                     Ok(player_ids) => player_ids,
-                    Err(_) => {
-                        return Err(Error::new(
-                            String::from("Internal server error"),
-                            Status::InternalServerError,
-                        ))
-                    }
+                    Err(e) => return Err(Error::storage(e)),
                 };
 
                 let mut players = Vec::new();
@@ -43,15 +49,11 @@ pub fn get_players_in_game(game_id: i32) -> Result<Vec<Player>, Error> {
                     let player = match players::table
                         .select(Player::as_select())
                         .filter(players::player_id.eq(player_id))
+                        .filter(players::game_id.eq(game_id))
                         .first::<Player>(&mut conn)
                     {
                         Ok(player) => player,
-                        Err(_) => {
-                            return Err(Error::new(
-                                String::from("Internal server error"),
-                                Status::InternalServerError,
-                            ))
-                        }
+                        Err(e) => return Err(Error::storage(e)),
                     };
                     players.push(player);
                 }
@@ -59,126 +61,410 @@ pub fn get_players_in_game(game_id: i32) -> Result<Vec<Player>, Error> {
                 Ok(players)
             } else {
                 Err(Error::new(
+                    ErrorKind::WrongGameStatus,
                     String::from("Game not in progress"),
-                    Status::BadRequest,
                 ))
             }
         }
-        Err(diesel::NotFound) => Err(Error::new(String::from("Game not found"), Status::NotFound)),
-        Err(_) => Err(Error::new(
-            String::from("Internal server error"),
-            Status::InternalServerError,
+        Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::GameNotFound,
+            String::from("Game not found"),
         )),
+        Err(e) => Err(Error::storage(e)),
     }
 }
 
-pub fn join_game(game_id: i32, player_id: String) -> Result<(), Error> {
-    let mut conn = establish_connection();
+pub fn join_game(
+    pools: &Pools,
+    game_id: i32,
+    player_id: String,
+    access: JoinGame,
+) -> Result<(), Error> {
+    let mut conn = pools.db()?;
     let game: Result<Game, DieselError> = games::table
         .select(Game::as_select())
         .find(game_id)
         .first::<Game>(&mut conn);
     match game {
         Ok(game) => {
+            if game.status != Some(GameStatus::to_string(GameStatus::WAITING)) {
+                return Err(Error::new(
+                    ErrorKind::WrongGameStatus,
+                    String::from("Game not in waiting state"),
+                ));
+            }
+
+            let banned = match game_bans::table
+                .count()
+                .filter(game_bans::game_id.eq(game_id))
+                .filter(game_bans::player_id.eq(player_id.clone()))
+                .get_result::<i64>(&mut conn)
+            {
+                Ok(banned) => banned > 0,
+                Err(e) => return Err(Error::storage(e)),
+            };
+            if banned {
+                return Err(Error::new(
+                    ErrorKind::PlayerBanned,
+                    String::from("Player is banned from this game"),
+                ));
+            }
+
+            // Players rejoining a lobby they are still part of do not need to show access again.
+            let is_member = match players::table
+                .count()
+                .filter(players::game_id.eq(game_id))
+                .filter(players::player_id.eq(player_id.clone()))
+                .filter(players::left_game_at.is_null())
+                .get_result::<i64>(&mut conn)
+            {
+                Ok(is_member) => is_member > 0,
+                Err(e) => return Err(Error::storage(e)),
+            };
+            if !is_member {
+                invite_service::check_access(&mut conn, &game, &player_id, &access)?;
+            }
+
             let players_amount: i64 = match players::table
                 .count()
                 .filter(players::game_id.eq(game_id))
+                .filter(players::left_game_at.is_null())
+                .filter(players::player_id.ne(player_id.clone()))
                 .first::<i64>(&mut conn)
             {
                 Ok(players_amount) => players_amount,
-                Err(_) => {
-                    return Err(Error::new(
-                        String::from("Internal server error"),
-                        Status::InternalServerError,
-                    ))
-                }
+                Err(e) => return Err(Error::storage(e)),
             };
-            if game.status == Some(GameStatus::to_string(GameStatus::WAITING))
-                && (game.max_players as i64) > players_amount
+            if (game.max_players as i64) <= players_amount {
+                return Err(Error::new(
+                    ErrorKind::GameFull,
+                    String::from("Game is full"),
+                ));
+            }
+
+            let new_player = NewPlayer {
+                player_id: player_id.clone(),
+                game_id,
+                is_host: Some(false),
+            };
+            match insert_into(players::table)
+                .values(&new_player)
+                .on_conflict((players::player_id, players::game_id))
+                .do_update()
+                .set(players::left_game_at.eq(None::<NaiveDateTime>))
+                .execute(&mut conn)
             {
-                let mut redis_conn = establish_redis_connection();
-                match redis_conn
-                    .sadd::<String, String, bool>(format!("game:{}:players", game_id), player_id)
-                {
-                    Ok(_) => Ok(()),
-                    Err(_) => Err(Error::new(
-                        String::from("Internal server error"),
-                        Status::InternalServerError,
-                    )),
-                }
-            } else {
-                Err(Error::new(
-                    String::from("Game not in waiting state"),
-                    Status::BadRequest,
-                ))
+                Ok(_) => (),
+                Err(e) => return Err(Error::storage(e)),
+            };
+
+            let mut redis_conn = pools.redis()?;
+            match redis_conn
+                .sadd::<String, String, bool>(format!("game:{}:players", game_id), player_id)
+            {
+                Ok(_) => Ok(()),
+                Err(e) => Err(Error::storage(e)),
             }
         }
-        Err(diesel::NotFound) => Err(Error::new(String::from("Game not found"), Status::NotFound)),
-        Err(_) => Err(Error::new(
-            String::from("Internal server error"),
-            Status::InternalServerError,
+        Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::GameNotFound,
+            String::from("Game not found"),
         )),
+        Err(e) => Err(Error::storage(e)),
     }
 }
 
-pub fn leave_game(game_id: i32, player_id: String) -> Result<(), Error> {
-    let mut redis_conn = establish_redis_connection();
+pub struct LeftGame {
+    pub new_host: Option<String>,
+    pub abandoned: bool,
+}
+
+// Hosting passes to the player who joined earliest among those still in the game, and a
+// running game nobody is left in is abandoned.
+pub fn leave_game(pools: &Pools, game_id: i32, player_id: String) -> Result<LeftGame, Error> {
+    let mut redis_conn = pools.redis()?;
     let is_member = redis_conn
-        .sismember::<String, String, bool>(format!("game:{}:player", game_id), player_id.clone());
+        .sismember::<String, String, bool>(format!("game:{}:players", game_id), player_id.clone());
     match is_member {
-        Ok(true) => {
-            match redis_conn.srem::<String, String, bool>(
-                format!("game:{}:players", game_id),
-                player_id.clone(),
-            ) {
-                Ok(_) => {
-                    let mut conn = establish_connection();
-                    let updated_player = UpdatePlayer {
-                        game_id,
-                        is_host: None,
-                        left_game_at: Some(chrono::Utc::now().naive_utc()),
-                        player_id: player_id.clone(),
-                    };
-                    let result = update(players::table)
-                        .filter(players::player_id.eq(player_id))
-                        .set(updated_player)
-                        .execute(&mut conn);
-                    match result {
-                        Ok(_) => Ok(()),
-                        Err(_) => Err(Error::new(
-                            String::from("Internal server error"),
-                            Status::InternalServerError,
-                        )),
-                    }
+        Ok(true) => (),
+        Ok(false) => {
+            return Err(Error::new(
+                ErrorKind::PlayerNotInGame,
+                String::from("Player not in game"),
+            ))
+        }
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    let mut conn = pools.db()?;
+    let result = conn.transaction::<LeftGame, DieselError, _>(|conn| {
+        let game = games::table
+            .select(Game::as_select())
+            .find(game_id)
+            .for_update()
+            .first::<Game>(conn)?;
+
+        let was_host = players::table
+            .select(players::is_host)
+            .filter(players::player_id.eq(player_id.clone()))
+            .filter(players::game_id.eq(game_id))
+            .first::<Option<bool>>(conn)?
+            .unwrap_or(false);
+
+        update(players::table)
+            .filter(players::player_id.eq(player_id.clone()))
+            .filter(players::game_id.eq(game_id))
+            .set(UpdatePlayer {
+                game_id,
+                is_host: Some(false),
+                left_game_at: Some(chrono::Utc::now().naive_utc()),
+                player_id: player_id.clone(),
+            })
+            .execute(conn)?;
+
+        let next_host = players::table
+            .select(players::player_id)
+            .filter(players::game_id.eq(game_id))
+            .filter(players::left_game_at.is_null())
+            .order((players::created_at, players::player_id))
+            .first::<String>(conn)
+            .optional()?;
+
+        match next_host {
+            Some(next_host) if was_host => {
+                update(players::table)
+                    .filter(players::player_id.eq(next_host.clone()))
+                    .filter(players::game_id.eq(game_id))
+                    .set(players::is_host.eq(true))
+                    .execute(conn)?;
+                Ok(LeftGame {
+                    new_host: Some(next_host),
+                    abandoned: false,
+                })
+            }
+            Some(_) => Ok(LeftGame {
+                new_host: None,
+                abandoned: false,
+            }),
+            None => {
+                let abandoned = game.status != Some(GameStatus::to_string(GameStatus::FINISHED));
+                if abandoned {
+                    update(games::table.find(game_id))
+                        .set(games::status.eq(GameStatus::to_string(GameStatus::ABANDONED)))
+                        .execute(conn)?;
                 }
-                Err(_) => Err(Error::new(
-                    String::from("Internal server error"),
-                    Status::InternalServerError,
-                )),
+                Ok(LeftGame {
+                    new_host: None,
+                    abandoned,
+                })
             }
         }
-        Ok(false) => Err(Error::new(
+    });
+
+    let left = match result {
+        Ok(left) => left,
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    let mut pipe = redis::pipe();
+    pipe.srem(format!("game:{}:players", game_id), player_id.clone())
+        .srem(format!("game:{}:ready", game_id), player_id);
+    if left.abandoned {
+        pipe.srem("active_games", game_id)
+            .del(format!("game:{}:status", game_id))
+            .del(format!("game:{}:ready", game_id));
+    }
+    match pipe.query::<()>(&mut *redis_conn) {
+        Ok(_) => Ok(left),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+pub fn kick_player(
+    pools: &Pools,
+    game_id: i32,
+    requester_id: String,
+    target_id: String,
+) -> Result<(), Error> {
+    remove_player(pools, game_id, requester_id, target_id, false)
+}
+
+// Banned players are removed like kicked ones and can not join the game again.
+pub fn ban_player(
+    pools: &Pools,
+    game_id: i32,
+    requester_id: String,
+    target_id: String,
+) -> Result<(), Error> {
+    remove_player(pools, game_id, requester_id, target_id, true)
+}
+
+fn remove_player(
+    pools: &Pools,
+    game_id: i32,
+    requester_id: String,
+    target_id: String,
+    ban: bool,
+) -> Result<(), Error> {
+    let mut conn = pools.db()?;
+    let game: Game = match games::table
+        .select(Game::as_select())
+        .find(game_id)
+        .first::<Game>(&mut conn)
+    {
+        Ok(game) => game,
+        Err(diesel::NotFound) => {
+            return Err(Error::new(
+                ErrorKind::GameNotFound,
+                String::from("Game not found"),
+            ))
+        }
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    if game.status != Some(GameStatus::to_string(GameStatus::WAITING)) {
+        return Err(Error::new(
+            ErrorKind::WrongGameStatus,
+            String::from("Players can only be removed from a lobby"),
+        ));
+    }
+
+    let is_host = match players::table
+        .select(players::is_host)
+        .filter(players::player_id.eq(requester_id.clone()))
+        .filter(players::game_id.eq(game_id))
+        .filter(players::left_game_at.is_null())
+        .first::<Option<bool>>(&mut conn)
+    {
+        Ok(is_host) => is_host == Some(true),
+        Err(diesel::NotFound) => false,
+        Err(e) => return Err(Error::storage(e)),
+    };
+    if !is_host {
+        return Err(Error::new(
+            ErrorKind::NotHost,
+            String::from("Only the host can remove players"),
+        ));
+    }
+    if requester_id == target_id {
+        return Err(Error::new(
+            ErrorKind::InvalidRequest,
+            String::from("The host can not remove themselves"),
+        ));
+    }
+
+    let mut redis_conn = pools.redis()?;
+    let is_member = match redis_conn
+        .sismember::<String, String, bool>(format!("game:{}:players", game_id), target_id.clone())
+    {
+        Ok(is_member) => is_member,
+        Err(e) => return Err(Error::storage(e)),
+    };
+    // A ban also keeps out players who are not in the lobby right now.
+    if !is_member && !ban {
+        return Err(Error::new(
+            ErrorKind::PlayerNotInGame,
             String::from("Player not in game"),
-            Status::BadRequest,
-        )),
-        Err(_) => Err(Error::new(
-            String::from("Internal server error"),
-            Status::InternalServerError,
-        )),
+        ));
+    }
+
+    let result = conn.transaction::<(), DieselError, _>(|conn| {
+        if ban {
+            insert_into(game_bans::table)
+                .values(&NewGameBan {
+                    game_id,
+                    player_id: target_id.clone(),
+                    banned_by: requester_id.clone(),
+                })
+                .on_conflict((game_bans::game_id, game_bans::player_id))
+                .do_nothing()
+                .execute(conn)?;
+        }
+        update(players::table)
+            .filter(players::player_id.eq(target_id.clone()))
+            .filter(players::game_id.eq(game_id))
+            .filter(players::left_game_at.is_null())
+            .set(players::left_game_at.eq(chrono::Utc::now().naive_utc()))
+            .execute(conn)?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        return Err(Error::storage(e));
+    }
+
+    match redis::pipe()
+        .srem(format!("game:{}:players", game_id), target_id.clone())
+        .srem(format!("game:{}:ready", game_id), target_id)
+        .query::<()>(&mut *redis_conn)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+pub fn set_ready(pools: &Pools, game_id: i32, player_id: String, ready: bool) -> Result<(), Error> {
+    let mut conn = pools.db()?;
+    let game: Game = match games::table
+        .select(Game::as_select())
+        .find(game_id)
+        .first::<Game>(&mut conn)
+    {
+        Ok(game) => game,
+        Err(diesel::NotFound) => {
+            return Err(Error::new(
+                ErrorKind::GameNotFound,
+                String::from("Game not found"),
+            ))
+        }
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    if game.status != Some(GameStatus::to_string(GameStatus::WAITING)) {
+        return Err(Error::new(
+            ErrorKind::WrongGameStatus,
+            String::from("Game not in waiting state"),
+        ));
+    }
+
+    let mut redis_conn = pools.redis()?;
+    match redis_conn
+        .sismember::<String, String, bool>(format!("game:{}:players", game_id), player_id.clone())
+    {
+        Ok(true) => (),
+        Ok(false) => {
+            return Err(Error::new(
+                ErrorKind::PlayerNotInGame,
+                String::from("Player not in game"),
+            ))
+        }
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    let key = format!("game:{}:ready", game_id);
+    let result = if ready {
+        redis_conn.sadd::<String, String, bool>(key, player_id)
+    } else {
+        redis_conn.srem::<String, String, bool>(key, player_id)
+    };
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::storage(e)),
     }
 }
 
 pub fn get_player_stats_per_game(
+    pools: &Pools,
     game_id: i32,
     player_id: String,
 ) -> Result<GamePlayerStats, Error> {
-    let mut conn: PgConnection = establish_connection();
+    let mut conn = pools.db()?;
     let game: Result<Game, DieselError> = games::table
         .select(Game::as_select())
         .find(game_id)
         .first::<Game>(&mut conn);
     match game {
         Ok(_) => {
+            check_player_in_game(&mut conn, game_id, &player_id)?;
             let player_stats: Vec<PlayerScoringRound> = match player_scoring_round::table
                 .select(PlayerScoringRound::as_select())
                 .filter(player_scoring_round::game_id.eq(game_id))
@@ -186,67 +472,13 @@ pub fn get_player_stats_per_game(
                 .get_results::<PlayerScoringRound>(&mut conn)
             {
                 Ok(player_stats) => player_stats,
-                Err(diesel::NotFound) => {
-                    return Err(Error::new(
-                        String::from("Player not found"),
-                        Status::NotFound,
-                    ))
-                }
-                Err(_) => {
-                    return Err(Error::new(
-                        String::from("Internal server error"),
-                        Status::InternalServerError,
-                    ))
-                }
+                Err(e) => return Err(Error::storage(e)),
             };
 
             let mut round_stats = Vec::new();
 
             for stat in player_stats {
-                let rnd_stats = PlayerRoundStats {
-                    round_id: stat.round_id,
-                    score: if stat.score == None {
-                        0
-                    } else {
-                        stat.score.unwrap()
-                    },
-                    place: if stat.place == None {
-                        0
-                    } else {
-                        stat.place.unwrap()
-                    },
-                    is_winner: if stat.is_winner == None {
-                        false
-                    } else {
-                        stat.is_winner.unwrap()
-                    },
-                    time_used_to_complete: if stat.time_used_to_complete == None {
-                        0
-                    } else {
-                        stat.time_used_to_complete.unwrap()
-                    },
-                    first_topic: if stat.first_topic == None {
-                        String::from("")
-                    } else {
-                        stat.first_topic.unwrap()
-                    },
-                    second_topic: if stat.second_topic == None {
-                        String::from("")
-                    } else {
-                        stat.second_topic.unwrap()
-                    },
-                    third_topic: if stat.third_topic == None {
-                        String::from("")
-                    } else {
-                        stat.third_topic.unwrap()
-                    },
-                    has_stopped_game: if stat.has_stopped_game == None {
-                        false
-                    } else {
-                        stat.has_stopped_game.unwrap()
-                    },
-                };
-                round_stats.push(rnd_stats);
+                round_stats.push(to_round_stats(stat));
             }
 
             let overall_stats = GamePlayerStats {
@@ -257,20 +489,21 @@ pub fn get_player_stats_per_game(
 
             Ok(overall_stats)
         }
-        Err(diesel::NotFound) => Err(Error::new(String::from("Game not found"), Status::NotFound)),
-        Err(_) => Err(Error::new(
-            String::from("Internal server error"),
-            Status::InternalServerError,
+        Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::GameNotFound,
+            String::from("Game not found"),
         )),
+        Err(e) => Err(Error::storage(e)),
     }
 }
 
 pub fn get_player_stats_per_round(
+    pools: &Pools,
     game_id: i32,
     round_id: i32,
     player_id: String,
 ) -> Result<PlayerScoringRound, Error> {
-    let mut conn = establish_connection();
+    let mut conn = pools.db()?;
     let player_stats_round: Result<PlayerScoringRound, DieselError> = player_scoring_round::table
         .select(PlayerScoringRound::as_select())
         .filter(player_scoring_round::game_id.eq(game_id))
@@ -280,91 +513,73 @@ pub fn get_player_stats_per_round(
     match player_stats_round {
         Ok(player_scoring) => Ok(player_scoring),
         Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::PlayerNotFound,
             String::from("Player not found"),
-            Status::NotFound,
-        )),
-        Err(_) => Err(Error::new(
-            String::from("Internal server error"),
-            Status::InternalServerError,
         )),
+        Err(e) => Err(Error::storage(e)),
     }
 }
 
-pub fn get_player_stats_per_game_all(game_id: i32) -> Result<Vec<GamePlayerStats>, Error> {
-    let mut conn = establish_connection();
+pub fn get_player_stats_per_game_all(
+    pools: &Pools,
+    game_id: i32,
+) -> Result<Vec<GamePlayerStats>, Error> {
+    let mut conn = pools.db()?;
+    // An empty list would otherwise hide an unknown game.
+    check_game(&mut conn, game_id)?;
     let player_stats: Result<Vec<PlayerScoringRound>, DieselError> = player_scoring_round::table
         .select(PlayerScoringRound::as_select())
         .filter(player_scoring_round::game_id.eq(game_id))
+        .order((
+            player_scoring_round::player_id,
+            player_scoring_round::round_id,
+        ))
         .get_results::<PlayerScoringRound>(&mut conn);
     match player_stats {
         Ok(player_stats) => {
-            let mut game_player_stats = Vec::new();
+            let mut game_player_stats: Vec<GamePlayerStats> = Vec::new();
             for stat in player_stats {
-                let game_player_stat = GamePlayerStats {
-                    game_id,
-                    player_id: stat.player_id.clone(),
-                    round_stats: vec![PlayerRoundStats {
-                        round_id: stat.round_id,
-                        score: if stat.score == None {
-                            0
-                        } else {
-                            stat.score.unwrap()
-                        },
-                        place: if stat.place == None {
-                            0
-                        } else {
-                            stat.place.unwrap()
-                        },
-                        is_winner: if stat.is_winner == None {
-                            false
-                        } else {
-                            stat.is_winner.unwrap()
-                        },
-                        time_used_to_complete: if stat.time_used_to_complete == None {
-                            0
-                        } else {
-                            stat.time_used_to_complete.unwrap()
-                        },
-                        first_topic: if stat.first_topic == None {
-                            String::from("")
-                        } else {
-                            stat.first_topic.unwrap()
-                        },
-                        second_topic: if stat.second_topic == None {
-                            String::from("")
-                        } else {
-                            stat.second_topic.unwrap()
-                        },
-                        third_topic: if stat.third_topic == None {
-                            String::from("")
-                        } else {
-                            stat.third_topic.unwrap()
-                        },
-                        has_stopped_game: if stat.has_stopped_game == None {
-                            false
-                        } else {
-                            stat.has_stopped_game.unwrap()
-                        },
-                    }],
-                };
-                game_player_stats.push(game_player_stat);
+                match game_player_stats.last_mut() {
+                    Some(last) if last.player_id == stat.player_id => {
+                        last.round_stats.push(to_round_stats(stat))
+                    }
+                    _ => game_player_stats.push(GamePlayerStats {
+                        game_id,
+                        player_id: stat.player_id.clone(),
+                        round_stats: vec![to_round_stats(stat)],
+                    }),
+                }
             }
 
+            // Ordered like the final standings, so the list reads as the game's result.
+            let totals = |stats: &GamePlayerStats| {
+                stats
+                    .round_stats
+                    .iter()
+                    .fold((0, 0), |(score, time), round| {
+                        (score + round.score, time + round.time_used_to_complete)
+                    })
+            };
+            game_player_stats.sort_by(|a, b| {
+                let (a_score, a_time) = totals(a);
+                let (b_score, b_time) = totals(b);
+                compare_results(a_score, a_time, b_score, b_time)
+            });
+
             Ok(game_player_stats)
         }
-        Err(diesel::NotFound) => Err(Error::new(String::from("Game not found"), Status::NotFound)),
-        Err(_) => Err(Error::new(
-            String::from("Internal server error"),
-            Status::InternalServerError,
-        )),
+        Err(e) => Err(Error::storage(e)),
     }
 }
 
 pub fn get_player_stats_per_round_all(
+    pools: &Pools,
     game_id: i32,
     round_id: i32,
 ) -> Result<Vec<PlayerScoringRound>, Error> {
-    let mut conn = establish_connection();
+    let mut conn = pools.db()?;
+    check_game(&mut conn, game_id)?;
+    check_round(&mut conn, game_id, round_id)?;
     let player_stats: Result<Vec<PlayerScoringRound>, DieselError> = player_scoring_round::table
         .select(PlayerScoringRound::as_select())
         .filter(player_scoring_round::game_id.eq(game_id))
@@ -372,10 +587,146 @@ pub fn get_player_stats_per_round_all(
         .get_results::<PlayerScoringRound>(&mut conn);
     match player_stats {
         Ok(player_stats) => Ok(player_stats),
-        Err(diesel::NotFound) => Err(Error::new(String::from("Game not found"), Status::NotFound)),
-        Err(_) => Err(Error::new(
-            String::from("Internal server error"),
-            Status::InternalServerError,
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+fn check_game(conn: &mut PgConnection, game_id: i32) -> Result<(), Error> {
+    match diesel::select(diesel::dsl::exists(games::table.find(game_id))).get_result::<bool>(conn) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Error::new(
+            ErrorKind::GameNotFound,
+            String::from("Game not found"),
+        )),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+fn check_round(conn: &mut PgConnection, game_id: i32, round_id: i32) -> Result<(), Error> {
+    match diesel::select(diesel::dsl::exists(
+        rounds::table
+            .filter(rounds::id.eq(round_id))
+            .filter(rounds::game_id.eq(game_id)),
+    ))
+    .get_result::<bool>(conn)
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Error::new(
+            ErrorKind::RoundNotFound,
+            String::from("Round not found"),
+        )),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+// Players who left keep their row, so their stats stay reachable.
+fn check_player_in_game(
+    conn: &mut PgConnection,
+    game_id: i32,
+    player_id: &str,
+) -> Result<(), Error> {
+    match diesel::select(diesel::dsl::exists(
+        players::table
+            .filter(players::player_id.eq(player_id.to_string()))
+            .filter(players::game_id.eq(game_id)),
+    ))
+    .get_result::<bool>(conn)
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Error::new(
+            ErrorKind::PlayerNotFound,
+            String::from("Player not found"),
         )),
+        Err(e) => Err(Error::storage(e)),
     }
 }
+
+pub fn to_round_stats(stat: PlayerScoringRound) -> PlayerRoundStats {
+    PlayerRoundStats {
+        round_id: stat.round_id,
+        score: stat.score.unwrap_or(0),
+        place: stat.place.unwrap_or(0),
+        is_winner: stat.is_winner.unwrap_or(false),
+        time_used_to_complete: stat.time_used_to_complete.unwrap_or(0),
+        first_topic: stat.first_topic.unwrap_or_default(),
+        second_topic: stat.second_topic.unwrap_or_default(),
+        third_topic: stat.third_topic.unwrap_or_default(),
+        has_stopped_game: stat.has_stopped_game.unwrap_or(false),
+    }
+}
+
+pub fn get_player_profile(pools: &Pools, player_id: String) -> Result<PlayerScoring, Error> {
+    let mut conn = pools.db()?;
+    match player_scoring::table
+        .select(PlayerScoring::as_select())
+        .filter(player_scoring::player_id.eq(player_id))
+        .first::<PlayerScoring>(&mut conn)
+    {
+        Ok(profile) => Ok(profile),
+        Err(diesel::NotFound) => Err(Error::new(
+            ErrorKind::PlayerNotFound,
+            String::from("Player not found"),
+        )),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+// Adds the outcome of a finished game to the lifetime scoring of every participant. The
+// standings have to be ordered by final placement.
+pub fn record_game_results(
+    conn: &mut PgConnection,
+    standings: &[GameStanding],
+    winner_id: Option<&str>,
+) -> QueryResult<()> {
+    let player_count = standings.len() as i32;
+
+    for (index, standing) in standings.iter().enumerate() {
+        let place = index as i32 + 1;
+        let won = winner_id == Some(standing.player_id.as_str());
+        let top3 = place <= 3;
+        let bottom3 = place > player_count - 3;
+        let xp = calculate_xp(place, player_count, won);
+
+        // The inserted row holds this game's results, an existing profile adds them up.
+        let new_profile = NewPlayerScoring {
+            player_id: standing.player_id.clone(),
+            total_xp: xp,
+            highest_score_game: standing.score,
+            highest_score_round: standing.best_round_score,
+            games_played: 1,
+            games_won: won as i32,
+            games_lost: !won as i32,
+            games_top3: top3 as i32,
+            games_bottom3: bottom3 as i32,
+        };
+        insert_into(player_scoring::table)
+            .values(new_profile)
+            .on_conflict(player_scoring::player_id)
+            .do_update()
+            .set((
+                player_scoring::total_xp
+                    .eq(player_scoring::total_xp + excluded(player_scoring::total_xp)),
+                player_scoring::highest_score_game.eq(greatest(
+                    player_scoring::highest_score_game,
+                    excluded(player_scoring::highest_score_game),
+                )),
+                player_scoring::highest_score_round.eq(greatest(
+                    player_scoring::highest_score_round,
+                    excluded(player_scoring::highest_score_round),
+                )),
+                player_scoring::games_played
+                    .eq(player_scoring::games_played + excluded(player_scoring::games_played)),
+                player_scoring::games_won
+                    .eq(player_scoring::games_won + excluded(player_scoring::games_won)),
+                player_scoring::games_lost
+                    .eq(player_scoring::games_lost + excluded(player_scoring::games_lost)),
+                player_scoring::games_top3
+                    .eq(player_scoring::games_top3 + excluded(player_scoring::games_top3)),
+                player_scoring::games_bottom3
+                    .eq(player_scoring::games_bottom3 + excluded(player_scoring::games_bottom3)),
+            ))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
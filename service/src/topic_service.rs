@@ -0,0 +1,154 @@
+use diesel::prelude::*;
+use infrastructure::{
+    models::{Game, NewTopic},
+    schema::{rounds, topics},
+    Pools,
+};
+use rand::seq::SliceRandom;
+use shared::{
+    error::{Error, ErrorKind},
+    topic::TopicDifficulty,
+};
+use std::fs;
+
+const BUNDLED_TOPICS: &str = include_str!("../data/topics.csv");
+
+pub fn load_bundled_topics(pools: &Pools) -> Result<usize, Error> {
+    load_topics(pools, BUNDLED_TOPICS)
+}
+
+pub fn load_topics_from_file(pools: &Pools, path: &str) -> Result<usize, Error> {
+    match fs::read_to_string(path) {
+        Ok(list) => load_topics(pools, &list),
+        Err(e) => Err(Error::with_cause(
+            ErrorKind::Configuration,
+            format!("Could not read topic list {}", path),
+            e,
+        )),
+    }
+}
+
+// Each line of a topic list reads `name,category,difficulty`; empty lines and lines
+// starting with `#` are skipped. Topics that already exist are left untouched.
+pub fn load_topics(pools: &Pools, list: &str) -> Result<usize, Error> {
+    let mut new_topics = Vec::new();
+
+    for line in list.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        match fields.as_slice() {
+            [name, category, difficulty] => {
+                let difficulty = TopicDifficulty::from_string(difficulty.to_string())?;
+                new_topics.push(NewTopic {
+                    name: name.to_string(),
+                    category: category.to_string(),
+                    difficulty: TopicDifficulty::to_string(difficulty),
+                });
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidRequest,
+                    format!("Invalid topic entry: {}", line),
+                ))
+            }
+        }
+    }
+
+    let mut conn = pools.db()?;
+    match diesel::insert_into(topics::table)
+        .values(&new_topics)
+        .on_conflict(topics::name)
+        .do_nothing()
+        .execute(&mut conn)
+    {
+        Ok(inserted) => Ok(inserted),
+        Err(e) => Err(Error::storage(e)),
+    }
+}
+
+// Makes sure a game with these settings can draw a different topic for each of its rounds.
+pub fn check_topics(
+    conn: &mut PgConnection,
+    category: Option<&str>,
+    difficulty: Option<&str>,
+    rounds: i32,
+) -> Result<(), Error> {
+    if let Some(category) = category {
+        let known = match diesel::select(diesel::dsl::exists(
+            topics::table.filter(topics::category.eq(category)),
+        ))
+        .get_result::<bool>(conn)
+        {
+            Ok(known) => known,
+            Err(e) => return Err(Error::storage(e)),
+        };
+        if !known {
+            return Err(Error::new(
+                ErrorKind::InvalidRequest,
+                format!("Unknown topic category: {}", category),
+            ));
+        }
+    }
+
+    let mut query = topics::table.count().into_boxed();
+    if let Some(category) = category {
+        query = query.filter(topics::category.eq(category));
+    }
+    if let Some(difficulty) = difficulty {
+        query = query.filter(topics::difficulty.eq(difficulty));
+    }
+    let available = match query.get_result::<i64>(conn) {
+        Ok(available) => available,
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    if available < rounds as i64 {
+        return Err(Error::new(
+            ErrorKind::InvalidRequest,
+            format!(
+                "Only {} topics match these settings, not enough for {} rounds",
+                available, rounds
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Picks a random topic matching the game's category and difficulty that has not been
+// drawn in one of the game's previous rounds yet.
+pub fn select_topic(conn: &mut PgConnection, game: &Game) -> Result<String, Error> {
+    let used_topics: Vec<String> = match rounds::table
+        .select(rounds::topic)
+        .filter(rounds::game_id.eq(game.id))
+        .get_results::<String>(conn)
+    {
+        Ok(used_topics) => used_topics,
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    let mut query = topics::table
+        .select(topics::name)
+        .filter(topics::name.ne_all(used_topics))
+        .into_boxed();
+    if let Some(category) = &game.topic_category {
+        query = query.filter(topics::category.eq(category));
+    }
+    if let Some(difficulty) = &game.topic_difficulty {
+        query = query.filter(topics::difficulty.eq(difficulty));
+    }
+
+    let candidates: Vec<String> = match query.get_results::<String>(conn) {
+        Ok(candidates) => candidates,
+        Err(e) => return Err(Error::storage(e)),
+    };
+
+    match candidates.choose(&mut rand::thread_rng()) {
+        Some(topic) => Ok(topic.clone()),
+        None => Err(Error::new(
+            ErrorKind::NoTopicsLeft,
+            String::from("No topics left for this game"),
+        )),
+    }
+}
@@ -1,17 +1,216 @@
 use rocket::http::Status;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Stable identifiers for every failure the services report. Clients should match on
+// these instead of the message, which is meant for humans and may change.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorKind {
+    InvalidRequest,
+    InvalidMessage,
+    Unauthorized,
+    GameNotFound,
+    PlayerNotFound,
+    RoundNotFound,
+    InviteNotFound,
+    MatchNotFound,
+    NotHost,
+    PlayerNotInGame,
+    WrongGameStatus,
+    GameFull,
+    WrongRound,
+    RoundAlreadyFinished,
+    AllRoundsPlayed,
+    NoTopicsLeft,
+    PlayerBanned,
+    NotEnoughPlayers,
+    PlayersNotReady,
+    JoinNotAllowed,
+    WrongPassword,
+    GamePaused,
+    RoundNotFinished,
+    Storage,
+    Unavailable,
+    Configuration,
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn code(&self) -> i32 {
+        match self {
+            ErrorKind::InvalidRequest => 1000,
+            ErrorKind::InvalidMessage => 1001,
+            ErrorKind::Unauthorized => 1002,
+            ErrorKind::GameNotFound => 2000,
+            ErrorKind::PlayerNotFound => 2001,
+            ErrorKind::RoundNotFound => 2002,
+            ErrorKind::InviteNotFound => 2003,
+            ErrorKind::MatchNotFound => 2004,
+            ErrorKind::NotHost => 3000,
+            ErrorKind::PlayerNotInGame => 3001,
+            ErrorKind::WrongGameStatus => 3002,
+            ErrorKind::GameFull => 3003,
+            ErrorKind::WrongRound => 3004,
+            ErrorKind::RoundAlreadyFinished => 3005,
+            ErrorKind::AllRoundsPlayed => 3006,
+            ErrorKind::NoTopicsLeft => 3007,
+            ErrorKind::PlayerBanned => 3008,
+            ErrorKind::NotEnoughPlayers => 3009,
+            ErrorKind::PlayersNotReady => 3010,
+            ErrorKind::JoinNotAllowed => 3011,
+            ErrorKind::WrongPassword => 3012,
+            ErrorKind::GamePaused => 3013,
+            ErrorKind::RoundNotFinished => 3014,
+            ErrorKind::Storage => 5000,
+            ErrorKind::Unavailable => 5001,
+            ErrorKind::Configuration => 5002,
+            ErrorKind::Internal => 5003,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ErrorKind::InvalidRequest | ErrorKind::InvalidMessage => Status::BadRequest,
+            ErrorKind::Unauthorized => Status::Unauthorized,
+            ErrorKind::GameNotFound
+            | ErrorKind::PlayerNotFound
+            | ErrorKind::RoundNotFound
+            | ErrorKind::InviteNotFound
+            | ErrorKind::MatchNotFound => Status::NotFound,
+            ErrorKind::NotHost
+            | ErrorKind::PlayerNotInGame
+            | ErrorKind::PlayerBanned
+            | ErrorKind::JoinNotAllowed
+            | ErrorKind::WrongPassword => Status::Forbidden,
+            ErrorKind::WrongGameStatus
+            | ErrorKind::GameFull
+            | ErrorKind::WrongRound
+            | ErrorKind::RoundAlreadyFinished
+            | ErrorKind::AllRoundsPlayed
+            | ErrorKind::NoTopicsLeft
+            | ErrorKind::NotEnoughPlayers
+            | ErrorKind::PlayersNotReady
+            | ErrorKind::GamePaused
+            | ErrorKind::RoundNotFinished => Status::Conflict,
+            ErrorKind::Unavailable => Status::ServiceUnavailable,
+            ErrorKind::Storage | ErrorKind::Configuration | ErrorKind::Internal => {
+                Status::InternalServerError
+            }
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Error {
+    pub kind: ErrorKind,
+    pub code: i32,
     pub message: String,
     pub status_code: Status,
+    // The underlying Diesel, Redis or pool error, kept for the logs only.
+    #[serde(skip)]
+    pub cause: Option<String>,
 }
 
 impl Error {
-    pub fn new(message: String, status_code: Status) -> Error {
+    pub fn new(kind: ErrorKind, message: String) -> Error {
         Error {
+            kind,
+            code: kind.code(),
             message,
-            status_code,
+            status_code: kind.status(),
+            cause: None,
+        }
+    }
+
+    pub fn with_cause<E: fmt::Display>(kind: ErrorKind, message: String, cause: E) -> Error {
+        Error {
+            cause: Some(cause.to_string()),
+            ..Error::new(kind, message)
+        }
+    }
+
+    pub fn storage<E: fmt::Display>(cause: E) -> Error {
+        Error::with_cause(
+            ErrorKind::Storage,
+            String::from("Internal server error"),
+            cause,
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.cause {
+            Some(cause) => write!(f, "{} ({}): {}", self.message, self.code, cause),
+            None => write!(f, "{} ({})", self.message, self.code),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Codes are part of the public protocol, changing one breaks clients.
+    const KINDS: [(ErrorKind, i32, Status); 27] = [
+        (ErrorKind::InvalidRequest, 1000, Status::BadRequest),
+        (ErrorKind::InvalidMessage, 1001, Status::BadRequest),
+        (ErrorKind::Unauthorized, 1002, Status::Unauthorized),
+        (ErrorKind::GameNotFound, 2000, Status::NotFound),
+        (ErrorKind::PlayerNotFound, 2001, Status::NotFound),
+        (ErrorKind::RoundNotFound, 2002, Status::NotFound),
+        (ErrorKind::InviteNotFound, 2003, Status::NotFound),
+        (ErrorKind::MatchNotFound, 2004, Status::NotFound),
+        (ErrorKind::NotHost, 3000, Status::Forbidden),
+        (ErrorKind::PlayerNotInGame, 3001, Status::Forbidden),
+        (ErrorKind::WrongGameStatus, 3002, Status::Conflict),
+        (ErrorKind::GameFull, 3003, Status::Conflict),
+        (ErrorKind::WrongRound, 3004, Status::Conflict),
+        (ErrorKind::RoundAlreadyFinished, 3005, Status::Conflict),
+        (ErrorKind::AllRoundsPlayed, 3006, Status::Conflict),
+        (ErrorKind::NoTopicsLeft, 3007, Status::Conflict),
+        (ErrorKind::PlayerBanned, 3008, Status::Forbidden),
+        (ErrorKind::NotEnoughPlayers, 3009, Status::Conflict),
+        (ErrorKind::PlayersNotReady, 3010, Status::Conflict),
+        (ErrorKind::JoinNotAllowed, 3011, Status::Forbidden),
+        (ErrorKind::WrongPassword, 3012, Status::Forbidden),
+        (ErrorKind::GamePaused, 3013, Status::Conflict),
+        (ErrorKind::RoundNotFinished, 3014, Status::Conflict),
+        (ErrorKind::Storage, 5000, Status::InternalServerError),
+        (ErrorKind::Unavailable, 5001, Status::ServiceUnavailable),
+        (ErrorKind::Configuration, 5002, Status::InternalServerError),
+        (ErrorKind::Internal, 5003, Status::InternalServerError),
+    ];
+
+    #[test]
+    fn kinds_keep_their_code_and_status() {
+        for (kind, code, status) in KINDS {
+            assert_eq!(kind.code(), code, "code of {:?}", kind);
+            assert_eq!(kind.status(), status, "status of {:?}", kind);
+        }
+    }
+
+    #[test]
+    fn codes_are_unique() {
+        for (index, (kind, code, _)) in KINDS.iter().enumerate() {
+            for (other, other_code, _) in &KINDS[index + 1..] {
+                assert!(
+                    code != other_code,
+                    "{:?} and {:?} share {}",
+                    kind,
+                    other,
+                    code
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn errors_carry_the_code_and_status_of_their_kind() {
+        let error = Error::new(ErrorKind::GameFull, String::from("Game is full"));
+        assert_eq!(error.code, 3003);
+        assert_eq!(error.status_code, Status::Conflict);
+        assert!(error.cause.is_none());
+    }
+}
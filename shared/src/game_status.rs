@@ -1,7 +1,9 @@
 pub enum GameStatus {
     WAITING,
     PLAYING,
+    PAUSED,
     FINISHED,
+    ABANDONED,
 }
 
 impl GameStatus {
@@ -9,7 +11,9 @@ impl GameStatus {
         match status.as_str() {
             "waiting" => GameStatus::WAITING,
             "playing" => GameStatus::PLAYING,
+            "paused" => GameStatus::PAUSED,
             "finished" => GameStatus::FINISHED,
+            "abandoned" => GameStatus::ABANDONED,
             _ => GameStatus::WAITING,
         }
     }
@@ -18,7 +22,9 @@ impl GameStatus {
         match status {
             GameStatus::WAITING => "waiting".to_string(),
             GameStatus::PLAYING => "playing".to_string(),
+            GameStatus::PAUSED => "paused".to_string(),
             GameStatus::FINISHED => "finished".to_string(),
+            GameStatus::ABANDONED => "abandoned".to_string(),
         }
     }
 }
@@ -0,0 +1,29 @@
+use crate::error::{Error, ErrorKind};
+
+pub enum GameVisibility {
+    PUBLIC,
+    PRIVATE,
+    FRIENDS,
+}
+
+impl GameVisibility {
+    pub fn from_string(visibility: String) -> Result<GameVisibility, Error> {
+        match visibility.as_str() {
+            "public" => Ok(GameVisibility::PUBLIC),
+            "private" => Ok(GameVisibility::PRIVATE),
+            "friends_only" => Ok(GameVisibility::FRIENDS),
+            _ => Err(Error::new(
+                ErrorKind::InvalidRequest,
+                String::from("Invalid game visibility"),
+            )),
+        }
+    }
+
+    pub fn to_string(visibility: GameVisibility) -> String {
+        match visibility {
+            GameVisibility::PUBLIC => "public".to_string(),
+            GameVisibility::PRIVATE => "private".to_string(),
+            GameVisibility::FRIENDS => "friends_only".to_string(),
+        }
+    }
+}
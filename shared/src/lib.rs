@@ -1,8 +1,10 @@
 pub mod error;
 pub mod game_status;
+pub mod game_visibility;
 pub mod request;
 pub mod response;
 pub mod score;
+pub mod topic;
 
 pub struct RequestOptions {
     pub limit: i32,
@@ -11,10 +13,7 @@ pub struct RequestOptions {
 
 impl RequestOptions {
     pub fn new(limit: Option<i32>, page: Option<i32>) -> RequestOptions {
-        let lim = match limit {
-            Some(limit) => limit,
-            None => 25,
-        };
+        let lim = limit.unwrap_or(25);
         let offset = match page {
             Some(page) => (page - 1) * lim,
             None => 0,
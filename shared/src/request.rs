@@ -1,21 +1,127 @@
-use crate::error::Error;
-use rocket::http::Status;
+use crate::error::{Error, ErrorKind};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
+// Messages sent by a client over the game stream, e.g.
+// `{"event": "finish_round", "data": {"round_number": 1, "first_topic": "cat", ...}}`.
 #[derive(Serialize, Deserialize)]
-pub struct Request {
-    pub event: String,
-    pub data: Option<RequestData>,
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum ClientMessage {
+    JoinGame(Option<JoinGame>),
+    LeaveGame,
+    StartGame,
+    FinishRound(RoundResult),
+    StartNextRound,
+    FinishGame,
+    SendRoundResult(RoundResult),
+    Resume(Resume),
+    KickPlayer(TargetPlayer),
+    BanPlayer(TargetPlayer),
+    SetReady,
+    UnsetReady,
+    RequestRematch,
+    PauseGame,
+    ResumeGame,
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct RequestData {
+#[serde(deny_unknown_fields)]
+pub struct RoundResult {
     pub round_number: Option<i32>,
-    pub first_topic: Option<String>,
-    pub second_topic: Option<String>,
-    pub third_topic: Option<String>,
+    pub first_topic: String,
+    pub second_topic: String,
+    pub third_topic: String,
+}
+
+// Needed to get into private or password protected games, the payload can be left out
+// otherwise.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct JoinGame {
+    pub password: Option<String>,
+    pub invite_code: Option<String>,
+}
+
+// Sent after reconnecting with the `seq` of the last event the client has seen. Live events
+// may already have arrived by then, so clients should pass `last_seq` when connecting instead.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Resume {
+    pub last_seq: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetPlayer {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateGame {
+    pub max_players: i32,
+    pub rounds: i32,
+    pub round_duration: Option<i32>,
+    pub visibility: Option<String>,
+    pub min_players: Option<i32>,
+    pub password: Option<String>,
+    pub topic_category: Option<String>,
+    pub topic_difficulty: Option<String>,
 }
 
+// Preferences of a player waiting for matchmaking, only players with the same
+// preferences end up in a game together.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct QueuePreferences {
+    pub max_players: i32,
+    pub rounds: i32,
+    pub topic_category: Option<String>,
+}
+
+impl ClientMessage {
+    pub fn parse(message: &str) -> Result<ClientMessage, Error> {
+        let message = match serde_json::from_str::<ClientMessage>(message) {
+            Ok(message) => message,
+            Err(e) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidMessage,
+                    format!("Invalid message: {}", e),
+                ))
+            }
+        };
+
+        match &message {
+            ClientMessage::FinishRound(result) | ClientMessage::SendRoundResult(result) => {
+                result.validate()?
+            }
+            _ => (),
+        }
+        Ok(message)
+    }
+}
+
+impl RoundResult {
+    fn validate(&self) -> Result<(), Error> {
+        if self
+            .round_number
+            .is_some_and(|round_number| round_number < 1)
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidMessage,
+                String::from("Invalid round number"),
+            ));
+        }
+        let topics = [&self.first_topic, &self.second_topic, &self.third_topic];
+        if topics.iter().any(|topic| topic.trim().is_empty()) {
+            return Err(Error::new(
+                ErrorKind::InvalidMessage,
+                String::from("Round results need a guess for every topic"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Names of the client events, used where an event is referenced without its payload.
 pub enum RequestEvent {
     JoinGame,
     LeaveGame,
@@ -24,34 +130,96 @@ pub enum RequestEvent {
     StartNextRound,
     FinishGame,
     SendRoundResult,
+    Resume,
+    KickPlayer,
+    BanPlayer,
+    SetReady,
+    UnsetReady,
+    RequestRematch,
+    PauseGame,
+    ResumeGame,
 }
 
-impl RequestEvent {
-    pub fn from_string(event: String) -> Result<RequestEvent, Error> {
-        match event.as_str() {
-            "join_game" => Ok(RequestEvent::JoinGame),
-            "leave_game" => Ok(RequestEvent::LeaveGame),
-            "start_game" => Ok(RequestEvent::StartGame),
-            "finish_round" => Ok(RequestEvent::FinishRound),
-            "start_next_round" => Ok(RequestEvent::StartNextRound),
-            "finish_game" => Ok(RequestEvent::FinishGame),
-            "send_round_result" => Ok(RequestEvent::SendRoundResult),
-            _ => Err(Error::new(
-                String::from("Invalid event type"),
-                Status::BadRequest,
-            )),
-        }
+impl fmt::Display for RequestEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let event = match self {
+            RequestEvent::JoinGame => "join_game",
+            RequestEvent::LeaveGame => "leave_game",
+            RequestEvent::StartGame => "start_game",
+            RequestEvent::FinishRound => "finish_round",
+            RequestEvent::StartNextRound => "start_next_round",
+            RequestEvent::FinishGame => "finish_game",
+            RequestEvent::SendRoundResult => "send_round_result",
+            RequestEvent::Resume => "resume",
+            RequestEvent::KickPlayer => "kick_player",
+            RequestEvent::BanPlayer => "ban_player",
+            RequestEvent::SetReady => "set_ready",
+            RequestEvent::UnsetReady => "unset_ready",
+            RequestEvent::RequestRematch => "request_rematch",
+            RequestEvent::PauseGame => "pause_game",
+            RequestEvent::ResumeGame => "resume_game",
+        };
+        write!(f, "{}", event)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn to_string(&self) -> String {
-        match self {
-            RequestEvent::JoinGame => "join_game".to_string(),
-            RequestEvent::LeaveGame => "leave_game".to_string(),
-            RequestEvent::StartGame => "start_game".to_string(),
-            RequestEvent::FinishRound => "finish_round".to_string(),
-            RequestEvent::StartNextRound => "start_next_round".to_string(),
-            RequestEvent::FinishGame => "finish_game".to_string(),
-            RequestEvent::SendRoundResult => "send_round_result".to_string(),
+    fn parse_error(message: &str) -> ErrorKind {
+        match ClientMessage::parse(message) {
+            Ok(_) => panic!("{} should be rejected", message),
+            Err(e) => e.kind,
         }
     }
+
+    #[test]
+    fn parses_events_with_and_without_payload() {
+        assert!(matches!(
+            ClientMessage::parse(r#"{"event": "start_game"}"#),
+            Ok(ClientMessage::StartGame)
+        ));
+        assert!(matches!(
+            ClientMessage::parse(r#"{"event": "join_game"}"#),
+            Ok(ClientMessage::JoinGame(None))
+        ));
+        assert!(matches!(
+            ClientMessage::parse(r#"{"event": "resume", "data": {"last_seq": 4}}"#),
+            Ok(ClientMessage::Resume(Resume { last_seq: 4 }))
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_events() {
+        assert_eq!(
+            parse_error(r#"{"event": "delete_game"}"#),
+            ErrorKind::InvalidMessage
+        );
+        assert_eq!(parse_error(r#"{"data": {}}"#), ErrorKind::InvalidMessage);
+        assert_eq!(parse_error("start_game"), ErrorKind::InvalidMessage);
+    }
+
+    #[test]
+    fn rejects_data_for_events_without_payload() {
+        assert_eq!(
+            parse_error(r#"{"event": "start_game", "data": {"round_number": 1}}"#),
+            ErrorKind::InvalidMessage
+        );
+        assert_eq!(
+            parse_error(r#"{"event": "set_ready", "data": "now"}"#),
+            ErrorKind::InvalidMessage
+        );
+    }
+
+    #[test]
+    fn rejects_empty_guesses() {
+        let message = r#"{"event": "finish_round", "data": {"round_number": 1,
+            "first_topic": "cat", "second_topic": "  ", "third_topic": "dog"}}"#;
+        assert_eq!(parse_error(message), ErrorKind::InvalidMessage);
+
+        let message = r#"{"event": "send_round_result", "data": {"round_number": 0,
+            "first_topic": "cat", "second_topic": "bird", "third_topic": "dog"}}"#;
+        assert_eq!(parse_error(message), ErrorKind::InvalidMessage);
+    }
 }
@@ -1,3 +1,4 @@
+use crate::error::{Error, ErrorKind};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
@@ -37,12 +38,69 @@ pub struct PlayerRoundStats {
     pub has_stopped_game: bool,
 }
 
+// Every message on the game stream shares this envelope: `event` names the message,
+// `data` carries its payload and errors fill `error`, `error_code` and `error_kind`.
 #[derive(Serialize, Deserialize)]
-pub struct RealtimeResponse<T> {
-    pub event: String,
-    pub data: Option<T>,
+pub struct RealtimeResponse {
+    #[serde(flatten)]
+    pub message: ServerMessage,
     pub error: Option<String>,
     pub error_code: Option<i32>,
+    pub error_kind: Option<ErrorKind>,
+    // Position of a broadcast in the game's event log; replies to a single client have none.
+    pub seq: Option<u64>,
+}
+
+impl RealtimeResponse {
+    pub fn new(message: ServerMessage) -> RealtimeResponse {
+        RealtimeResponse {
+            message,
+            error: None,
+            error_code: None,
+            error_kind: None,
+            seq: None,
+        }
+    }
+
+    pub fn with_seq(message: ServerMessage, seq: u64) -> RealtimeResponse {
+        RealtimeResponse {
+            seq: Some(seq),
+            ..RealtimeResponse::new(message)
+        }
+    }
+
+    pub fn error(error: Error) -> RealtimeResponse {
+        RealtimeResponse {
+            message: ServerMessage::Error,
+            error_code: Some(error.code),
+            error_kind: Some(error.kind),
+            error: Some(error.message),
+            seq: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum ServerMessage {
+    PlayerJoined(PlayerJoined),
+    PlayerLeft(PlayerLeft),
+    GameStarted(GameStarted),
+    RoundFinished(RoundFinished),
+    NextRoundStarted(NextRoundStarted),
+    GameFinished(GameFinished),
+    RoundResultSent(RoundResultSent),
+    GameState(GameState),
+    HostChanged(HostChanged),
+    GameAbandoned(GameAbandoned),
+    PlayerKicked(PlayerKicked),
+    PlayerReady(PlayerReady),
+    MatchFound(MatchFound),
+    RematchRequested(RematchRequested),
+    RematchStarted(RematchStarted),
+    GamePaused(GamePaused),
+    GameResumed(GameResumed),
+    Error,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -55,6 +113,68 @@ pub struct PlayerLeft {
     pub player_id: String,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct PlayerReady {
+    pub player_id: String,
+    pub ready: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlayerKicked {
+    pub player_id: String,
+    pub banned: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MatchFound {
+    pub game_id: i32,
+    pub game_slug: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RematchRequested {
+    pub player_id: String,
+    pub accepted: usize,
+    pub needed: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RematchStarted {
+    pub game_id: i32,
+    pub game_slug: String,
+    pub previous_game_id: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RematchStatus {
+    pub accepted: usize,
+    pub needed: usize,
+    pub game_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HostChanged {
+    pub player_id: String,
+}
+
+// `remaining_time` is the time left on the open round in milliseconds, if there is one.
+#[derive(Serialize, Deserialize)]
+pub struct GamePaused {
+    pub game_status: String,
+    pub remaining_time: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GameResumed {
+    pub game_status: String,
+    pub remaining_time: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GameAbandoned {
+    pub game_status: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct GameStarted {
     pub game_status: String,
@@ -94,44 +214,35 @@ pub struct RoundResultSent {
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct ErrorRes {}
-
-pub enum ResponseEvents {
-    PlayerJoined,
-    PlayerLeft,
-    GameStarted,
-    RoundFinished,
-    NextRoundStarted,
-    GameFinished,
-    RoundResultSent,
-    Error,
+pub struct GameState {
+    pub game_id: i32,
+    pub game_slug: String,
+    pub game_status: String,
+    pub max_players: i32,
+    pub min_players: Option<i32>,
+    pub rounds: i32,
+    pub round_duration: i32,
+    pub winner_id: Option<String>,
+    pub players: Vec<PlayerState>,
+    pub current_round: Option<RoundState>,
 }
 
-impl ResponseEvents {
-    pub fn to_string(&self) -> String {
-        match self {
-            ResponseEvents::PlayerJoined => "player_joined".to_string(),
-            ResponseEvents::PlayerLeft => "player_left".to_string(),
-            ResponseEvents::GameStarted => "game_started".to_string(),
-            ResponseEvents::RoundFinished => "round_finished".to_string(),
-            ResponseEvents::NextRoundStarted => "next_round_started".to_string(),
-            ResponseEvents::GameFinished => "game_finished".to_string(),
-            ResponseEvents::RoundResultSent => "round_result_sent".to_string(),
-            ResponseEvents::Error => "error".to_string(),
-        }
-    }
+#[derive(Serialize, Deserialize)]
+pub struct PlayerState {
+    pub player_id: String,
+    pub is_host: bool,
+    pub is_ready: bool,
+}
 
-    pub fn from_string(event: String) -> ResponseEvents {
-        match event.as_str() {
-            "player_joined" => ResponseEvents::PlayerJoined,
-            "player_left" => ResponseEvents::PlayerLeft,
-            "game_started" => ResponseEvents::GameStarted,
-            "round_finished" => ResponseEvents::RoundFinished,
-            "next_round_started" => ResponseEvents::NextRoundStarted,
-            "game_finished" => ResponseEvents::GameFinished,
-            "round_result_sent" => ResponseEvents::RoundResultSent,
-            "error" => ResponseEvents::Error,
-            _ => ResponseEvents::PlayerJoined,
-        }
-    }
+#[derive(Serialize, Deserialize)]
+pub struct RoundState {
+    pub round_id: i32,
+    pub round_number: i32,
+    pub topic: String,
+    pub start_time: i64,
+    pub end_time: Option<i64>,
+    // Milliseconds left until the round times out, zero once it has ended.
+    pub remaining_time: i64,
+    // Players who already sent their guesses for this round.
+    pub submitted: Vec<String>,
 }
@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+
 pub fn calculate_score(
     topic: String,
     first_topic: String,
@@ -14,7 +16,55 @@ pub fn calculate_score(
         score += 300;
     }
     if has_stopped_game {
-        score = score * 2;
+        score *= 2;
     }
     score
 }
+
+// Every finished game is worth some experience, more the better the final placement, with
+// an extra bonus for the winner. A game can end without a winner, so the bonus follows `won`
+// rather than the placement.
+pub fn calculate_xp(place: i32, player_count: i32, won: bool) -> i32 {
+    let mut xp = 50 + (player_count - place) * 25;
+    if won {
+        xp += 100;
+    }
+    xp
+}
+
+// Orders results with the higher score first, the lower completion time winning a tie.
+pub fn compare_results(a_score: i32, a_time: i64, b_score: i32, b_time: i64) -> Ordering {
+    b_score.cmp(&a_score).then_with(|| a_time.cmp(&b_time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xp_grows_with_the_placement() {
+        assert_eq!(calculate_xp(1, 4, true), 225);
+        assert_eq!(calculate_xp(2, 4, false), 100);
+        assert_eq!(calculate_xp(4, 4, false), 50);
+        // A single player still wins the game.
+        assert_eq!(calculate_xp(1, 1, true), 150);
+    }
+
+    #[test]
+    fn first_place_without_a_winner_gets_no_bonus() {
+        assert_eq!(calculate_xp(1, 4, false), 125);
+    }
+
+    #[test]
+    fn higher_score_ranks_first() {
+        assert_eq!(compare_results(500, 90_000, 400, 1_000), Ordering::Less);
+        assert_eq!(compare_results(0, 0, 300, 60_000), Ordering::Greater);
+    }
+
+    #[test]
+    fn tie_goes_to_the_lower_time() {
+        assert_eq!(compare_results(500, 20_000, 500, 30_000), Ordering::Less);
+        assert_eq!(compare_results(500, 30_000, 500, 20_000), Ordering::Greater);
+        assert_eq!(compare_results(500, 20_000, 500, 20_000), Ordering::Equal);
+    }
+}
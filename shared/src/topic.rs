@@ -0,0 +1,29 @@
+use crate::error::{Error, ErrorKind};
+
+pub enum TopicDifficulty {
+    EASY,
+    MEDIUM,
+    HARD,
+}
+
+impl TopicDifficulty {
+    pub fn from_string(difficulty: String) -> Result<TopicDifficulty, Error> {
+        match difficulty.as_str() {
+            "easy" => Ok(TopicDifficulty::EASY),
+            "medium" => Ok(TopicDifficulty::MEDIUM),
+            "hard" => Ok(TopicDifficulty::HARD),
+            _ => Err(Error::new(
+                ErrorKind::InvalidRequest,
+                String::from("Invalid topic difficulty"),
+            )),
+        }
+    }
+
+    pub fn to_string(difficulty: TopicDifficulty) -> String {
+        match difficulty {
+            TopicDifficulty::EASY => "easy".to_string(),
+            TopicDifficulty::MEDIUM => "medium".to_string(),
+            TopicDifficulty::HARD => "hard".to_string(),
+        }
+    }
+}